@@ -1,15 +1,25 @@
 use crate::model::*;
+use crate::trimesh::TriMesh;
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::mpsc::{channel, Receiver, TryRecvError};
 
-#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
 pub struct ModelRef(usize);
 
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+pub struct MaterialRef(usize);
+
 pub struct Assets {
     asset_root: PathBuf,
     models: HashMap<ModelRef, Model>,
     model_refs: HashMap<PathBuf, ModelRef>,
+    materials: HashMap<MaterialRef, Rc<Material>>,
+    material_refs: HashMap<String, MaterialRef>,
+    trimeshes: HashMap<ModelRef, Rc<TriMesh>>,
     rx: Receiver<notify::DebouncedEvent>,
 }
 impl Assets {
@@ -30,6 +40,9 @@ impl Assets {
             asset_root: asset_root.as_ref().to_owned(),
             models: HashMap::new(),
             model_refs: HashMap::new(),
+            materials: HashMap::new(),
+            material_refs: HashMap::new(),
+            trimeshes: HashMap::new(),
             rx,
         }
     }
@@ -45,6 +58,7 @@ impl Assets {
         if let Some(mref) = self.model_refs.get(p) {
             self.models
                 .insert(*mref, Model::load(device, queue, layout, &p).unwrap());
+            self.trimeshes.remove(mref);
         };
     }
     pub fn check_events(
@@ -107,4 +121,71 @@ impl Assets {
     pub fn get_model(&self, model: ModelRef) -> Option<&Model> {
         self.models.get(&model)
     }
+    pub fn load_material(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        tex: impl AsRef<Path>,
+    ) -> MaterialRef {
+        let mref = self.material_ref_for(name);
+        self.materials.entry(mref).or_insert_with(|| {
+            Material::load(name.to_string(), device, queue, layout, tex).unwrap()
+        });
+        mref
+    }
+    pub fn material_ref_for(&mut self, name: &str) -> MaterialRef {
+        let new_ref = MaterialRef(self.material_refs.len());
+        *self.material_refs.entry(name.to_string()).or_insert(new_ref)
+    }
+    pub fn get_material(&self, material: MaterialRef) -> Option<&Rc<Material>> {
+        self.materials.get(&material)
+    }
+    /// A collision mesh with the same triangles as `model`.  Works for
+    /// models that were only registered, like when running headless, by
+    /// reading just the geometry from the file.
+    pub fn trimesh(&mut self, model: ModelRef) -> Rc<TriMesh> {
+        if let Some(mesh) = self.trimeshes.get(&model) {
+            return Rc::clone(mesh);
+        }
+        let mesh = Rc::new(match self.models.get(&model) {
+            Some(m) => TriMesh::from_triangles(&m.triangles),
+            None => {
+                let path = self.asset_root.join(self.path_for_model_ref(model));
+                TriMesh::from_triangles(&Triangles::load(path).unwrap())
+            }
+        });
+        self.trimeshes.insert(model, Rc::clone(&mesh));
+        mesh
+    }
+}
+
+/// Read some plain data, as RON if the file ends in `.ron` and JSON
+/// otherwise.  `what` is only for error messages.
+pub fn load_data<T: DeserializeOwned>(path: impl AsRef<Path>, what: &str) -> Result<T> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read {} {:?}", what, path))?;
+    let data = if is_ron(path) {
+        ron::de::from_str(&text).with_context(|| format!("Bad {} {:?}", what, path))?
+    } else {
+        serde_json::from_str(&text).with_context(|| format!("Bad {} {:?}", what, path))?
+    };
+    Ok(data)
+}
+
+/// Write some plain data out, in the same format `load_data` would pick
+pub fn save_data<T: Serialize>(data: &T, path: impl AsRef<Path>, what: &str) -> Result<()> {
+    let path = path.as_ref();
+    let text = if is_ron(path) {
+        ron::ser::to_string_pretty(data, ron::ser::PrettyConfig::default())?
+    } else {
+        serde_json::to_string_pretty(data)?
+    };
+    std::fs::write(path, text).with_context(|| format!("Couldn't write {} {:?}", what, path))
+}
+
+fn is_ron(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "ron")
 }
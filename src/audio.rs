@@ -4,13 +4,17 @@ use std::thread;
 use crate::geom::{Pos3};
 use crate::music::Sound;
 
+// Whether to play, where from, and what
+type AudioMessage = (bool, f32, f32, f32, Option<Sound>);
+
 pub struct Audio {
-    control_channel: Sender<(bool, f32, f32, f32, Option<Sound>)>
+    // None when audio is disabled, e.g. when running headless
+    control_channel: Option<Sender<AudioMessage>>
 }
 
 impl Audio {
     pub fn new() -> Self {
-        let (tx, rx): (Sender<(bool, f32, f32, f32, Option<Sound>)>, Receiver<(bool, f32, f32, f32, Option<Sound>)>) = mpsc::channel();
+        let (tx, rx): (Sender<AudioMessage>, Receiver<AudioMessage>) = mpsc::channel();
         thread::spawn(move || {
             let (_stream, stream_handle) = rodio::OutputStream::try_default().unwrap();
             let sink = Sound::spatial_sink(&stream_handle, [-2.0, 1.5, -3.0], [-19.0, 5.0, -20.0], [-21.0, 5.0, -20.0]);
@@ -25,12 +29,25 @@ impl Audio {
             }
         });
         Self {
-            control_channel: tx
+            control_channel: Some(tx)
+        }
+    }
+
+    /// An audio sink that never opens an output device and drops every sound.
+    pub fn disabled() -> Self {
+        Self {
+            control_channel: None
         }
     }
 
+    pub fn is_enabled(&self) -> bool {
+        self.control_channel.is_some()
+    }
+
     pub fn play(&self, pos: Pos3, audio_source: Sound) {
-        self.control_channel.send((true, pos.x, pos.y, pos.z, Some(audio_source))).unwrap();
-        self.control_channel.send((false, pos.x, pos.y, pos.z, None)).unwrap();
+        if let Some(control_channel) = &self.control_channel {
+            control_channel.send((true, pos.x, pos.y, pos.z, Some(audio_source))).unwrap();
+            control_channel.send((false, pos.x, pos.y, pos.z, None)).unwrap();
+        }
     }
 }
\ No newline at end of file
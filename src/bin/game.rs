@@ -1,8 +1,6 @@
-use cgmath::prelude::*;
 // use game3d_engine::model;
 
 
-use std::rc::Rc;
 
 
 
@@ -15,11 +13,17 @@ use std::io::prelude::*;
 
 
 
-use game3d_engine::{Engine, Game, audio::Audio, model::Material, music::Sound, render::InstanceGroups, text::Sentence};
+use game3d_engine::{Engine, Game, assets::MaterialRef, music::Sound, render::{InstanceGroups, RenderContext}, text::Sentence};
 
 
 
-use game3d_engine::shapes::{Ball, Goal, Static};
+use game3d_engine::shapes::{Ball, Static, StaticShape};
+
+use game3d_engine::assets::ModelRef;
+
+use game3d_engine::ecs::{Entity, World};
+
+use game3d_engine::scene::{Level, Scene};
 
 
 
@@ -27,46 +31,63 @@ use game3d_engine::camera_control::CameraController;
 
 use game3d_engine::geom::*;
 
-use game3d_engine::collision::{CollisionDetection};
+use game3d_engine::collision::{Body, CollisionDetection, CollisionEvent, ContactPhase};
 
-use game3d_engine::physics::{BallMovement, Physics};
+use game3d_engine::interpolate::{update_transforms, Interpolated};
 
-use game3d_engine::events::Events;
+use game3d_engine::physics::{BallMovement, Integrator, Physics};
 
-use winit::event::VirtualKeyCode;
+use game3d_engine::schedule::{Schedule, Stage, SystemDesc};
 
+use game3d_engine::state::{State, StateStack, Transition};
 
-enum Mode {
-    TitleScreen,
-    GamePlay,
-    EndGame,
-}
+use game3d_engine::input::{Bindings, Input};
 
-struct GameData {
-    ball_model: game3d_engine::assets::ModelRef,
-    wall_model: game3d_engine::assets::ModelRef,
-    floor_model: game3d_engine::assets::ModelRef,
-    goal_model: game3d_engine::assets::ModelRef,
-}
+use game3d_engine::replay::Recording;
+
+use game3d_engine::rng::Rngs;
+
+use game3d_engine::config::EngineConfig;
+
+use serde::{Deserialize, Serialize};
+
+
+// Slowest a ball can hit a wall and still make a sound
+const MIN_CLACK_SPEED: f32 = 0.5;
+
+// The controls the game ships with, and where the player's own go once
+// they rebind something
+const DEFAULT_BINDINGS: &str = "content/bindings.ron";
+const BINDINGS: &str = "bindings.ron";
 
 pub struct Components {
-    balls: Vec<Ball>,     // game specific
-    statics: Vec<Static>, // game specific
-    goal: Goal,           // game specific
-    meter: Vec<(Rect, f32, Rc<Material>)>,
-    physics: Vec<Physics>, // in engine
-    models: GameData,      // in engine
+    world: World, // balls, statics and what they're drawn with
+    level: Level, // which entities the level made, and the goal
+    player: Entity,
+    bin: Entity, // the dustbin
+    ball_movement: BallMovement,
+    // everything that happened in this frame's collision step
+    collisions: Vec<CollisionEvent>,
+    meter: Vec<(Rect, f32, MaterialRef)>,
     score: usize,
-    sounds: Vec<(Sound, bool)>,
+    sounds: Vec<Sound>,
     text: Vec<Sentence>,
-    text_mat: Rc<Material>,
-    menu: (Rect, Rect, Rc<Material>),
+    text_mat: MaterialRef,
+    menu: (Rect, Rect, MaterialRef),
     camera: CameraController, // in engine
-    mode: Mode
+    // has the player left the title screen?  Nothing to autosave until then
+    playing: bool,
 }
 
 impl Components {
     pub fn new(engine: &mut Engine) -> Self {
+        let bindings = if Path::new(BINDINGS).exists() {
+            BINDINGS
+        } else {
+            DEFAULT_BINDINGS
+        };
+        engine.events.bindings = Bindings::load(bindings).unwrap();
+
         let empty_meter = engine.load_material("empty-meter", "content/empty-meter.png");
         let full_meter = engine.load_material("full-meter", "content/full-meter.png");
         let menu = engine.load_material("menu", "content/menu.png");
@@ -95,57 +116,18 @@ impl Components {
             ),
         ];
 
-        let balls = vec![Ball {
-            body: Sphere {
-                c: Pos3::new(-20.0, 5.0, -20.0),
-                r: 0.1,
-            },
-            pitch: 0.0,
-            yaw: 0.0,
-            mass: 4.0 * 3.14 * (0.3_f32).powf(3.0) / 3.0,
-            play: false,
-        }];
-
-        let walls = vec![
-            Static {
-                body: Plane {
-                    n: Vec3::new(0.0, 1.0, 0.0),
-                    d: 2.0,
-                },
-                position: Vec3::new(0.0, -0.025, 0.0),
-            },
-            Static {
-                body: Plane {
-                    n: Vec3::new(0.0, 0.0, -1.0),
-                    d: 2.0,
-                },
-                position: Vec3::new(0.0, -0.025, 0.0),
-            },
-            Static {
-                body: Plane {
-                    n: Vec3::new(-1.0, 0.0, 0.0),
-                    d: 2.0,
-                },
-                position: Vec3::new(0.0, -0.025, 0.0),
-            },
-        ];
-        let goal = Goal {
-            body: Box {
-                c: Pos3::new(-2.0, 1.5, -3.0),
-                r: Pos3::new(0.5, 0.5, 0.5),
-            },
-        };
-        let physics = vec![Physics {
-            velocity: Vec3::zero(),
-            momentum: Vec3::zero(),
-            force: Vec3::zero(),
-        }];
-        let game_data = GameData {
-            ball_model: engine.load_model("sphere.obj"),
-            wall_model: engine.load_model("wall.obj"),
-            floor_model: engine.load_model("floor.obj"),
-            goal_model: engine.load_model("dustbin.obj"),
-        };
+        let mut world = World::new();
+        world.register::<Ball>();
+        world.register::<Physics>();
+        world.register::<Static>();
+        world.register::<ModelRef>();
+        world.register::<Interpolated>();
+        let level = Scene::load("content/level.ron")
+            .and_then(|scene| scene.instantiate(engine, &mut world))
+            .unwrap();
+        let player = level.balls[0];
+        // The goal moves, and the bin it sits in has to go with it
+        let bin = level.named_statics["bin"];
 
         let power_text = Sentence::text_to_sentence("Power", [-0.8, -0.55]);
         let text = vec![power_text];
@@ -154,174 +136,368 @@ impl Components {
         
         let collide_sound = Sound::load("content/ball_collide.mp3").unwrap();
         
-        let sounds = vec![(collide_sound, true)];
+        let sounds = vec![collide_sound];
         Components {
-            balls: balls,
-            statics: walls,
-            goal: goal,
+            world,
+            level,
+            player,
+            bin,
+            ball_movement: BallMovement::new(),
+            collisions: vec![],
             meter: meter,
-            physics: physics,
-            models: game_data,
             score: 0,
             sounds: sounds,
             text: text,
             text_mat: text_mat,
             menu: (Rect { x: -0.9, y: -0.9, w: 1.8, h: 1.8 }, Rect {x: 0.0, y: 0.0, w: 1.0, h: 1.0}, menu),
             camera: camera,
-            mode: Mode::TitleScreen
+            playing: false,
         }
     }
 }
 
-pub struct Systems {
-    ball_movement: BallMovement, // game specific
-    collision_detection: CollisionDetection, // in engine
-}
+pub type Systems = Schedule<Components>;
 
-impl Systems {
-    pub fn new() -> Self {
-        Systems {
-            ball_movement: BallMovement::new(),
-            collision_detection: CollisionDetection::new(),
+fn systems() -> Systems {
+    let mut schedule = Schedule::new();
+    schedule.add(SystemDesc::new("aim", Stage::Input, |engine: &mut Engine, c: &mut Components| {
+        let player = c.player;
+        if let Some(ball) = c.world.storage_mut::<Ball>().get_mut(player) {
+            c.camera.update(&engine.events, ball);
         }
-    }
-    pub fn process(&mut self, events: &Events, c: &mut Components, sink: &Audio) {
-        self.ball_movement
-            .update(events, &mut c.balls, &mut c.meter[1], &mut c.physics);
-        let effect =
-            self.collision_detection
-                .update(&c.statics, &mut c.balls, &c.goal, &mut c.physics);
-
-        // println!("effect: {:?}", effect);
-        match effect {
-            game3d_engine::collision::CollisionEffect::Score => {
+        c.camera.update_camera(engine.camera_mut());
+    }));
+    schedule.add(
+        SystemDesc::new("shoot", Stage::Input, |engine: &mut Engine, c: &mut Components| {
+            c.ball_movement.update(
+                &engine.events,
+                &mut c.world.storage_mut(),
+                &mut c.meter[1],
+                &mut c.world.storage_mut(),
+                engine.dt(),
+            );
+        })
+        // Shots go wherever the ball is aimed this frame
+        .after("aim"),
+    );
+
+    let integrator = Integrator::new();
+    schedule.add(SystemDesc::new("integrate", Stage::Physics, move |engine: &mut Engine, c: &mut Components| {
+        integrator.update(&mut c.world.storage_mut::<Ball>(), &mut c.world.storage_mut(), engine.dt());
+    }));
+    let mut collision_detection = CollisionDetection::new();
+    // Full power shots move the ball several times its size per frame
+    collision_detection.ccd = true;
+    schedule.add(
+        SystemDesc::new("collide", Stage::Physics, move |engine: &mut Engine, c: &mut Components| {
+            let collisions = collision_detection.update(
+                &c.world.storage(),
+                &mut c.world.storage_mut(),
+                &c.level.goal,
+                &mut c.world.storage_mut(),
+                engine.dt(),
+            );
+            c.collisions.clear();
+            c.collisions.extend_from_slice(collisions);
+        })
+        .after("integrate"),
+    );
+
+    schedule.add(SystemDesc::new("score", Stage::PostPhysics, |engine: &mut Engine, c: &mut Components| {
+        let mut balls = c.world.storage_mut::<Ball>();
+        let mut physics = c.world.storage_mut::<Physics>();
+        for ev in entered(&c.collisions) {
+            if let (Body::Ball(ball), Body::Goal) = (ev.a, ev.b) {
                 c.score += 1;
-                c.balls[0].play = false;
-                self.ball_movement.player_mag = 0.0;
-                c.physics[0].reset();
+                balls.get_mut(ball).unwrap().play = false;
+                c.ball_movement.player_mag = 0.0;
+                physics.get_mut(ball).unwrap().reset();
+                c.meter[1].0.w = 0.0;
+                c.meter[1].1 = 0.0;
+                c.level.goal.gen_new_loc(engine.rng.stream("goal"));
+                let mut statics = c.world.storage_mut::<Static>();
+                if let Some(StaticShape::Mesh(m)) =
+                    statics.get_mut(c.bin).map(|bin| &mut bin.body)
+                {
+                    m.c = c.level.goal.body.c;
+                }
+            }
+        }
+    }));
+    schedule.add(
+        SystemDesc::new("reset", Stage::PostPhysics, |engine: &mut Engine, c: &mut Components| {
+            if engine.events.action_released("reset") {
+                c.world.storage_mut::<Ball>().get_mut(c.player).unwrap().play = false;
+                c.ball_movement.player_mag = 0.0;
+                c.world.storage_mut::<Physics>().get_mut(c.player).unwrap().reset();
                 c.meter[1].0.w = 0.0;
                 c.meter[1].1 = 0.0;
-                c.goal.gen_new_loc();
-                c.sounds[0].1 = true;
-            },
-            game3d_engine::collision::CollisionEffect::WallCollision => {
-                // println!("make it here");
-                if c.sounds[0].1 {
-
-                    let pos = c.balls[0].body.c;
-                    // c.sink.set_volume(2.0);
-                    // sink.set_emitter_position([pos.x, pos.y, pos.z]);
-                    // sink.append(c.sounds[0].0.decoder());
-                    sink.play(pos, c.sounds[0].0.clone());
-                    c.sounds[0].1 = false;
+            }
+        })
+        .after("score"),
+    );
+
+    schedule.add(SystemDesc::new("transforms", Stage::RenderPrep, |_: &mut Engine, c: &mut Components| {
+        update_transforms(&c.world.storage::<Ball>(), &mut c.world.storage_mut());
+    }));
+
+    schedule.add(SystemDesc::new("clack", Stage::Audio, |engine: &mut Engine, c: &mut Components| {
+        let balls = c.world.storage::<Ball>();
+        for ev in entered(&c.collisions) {
+            match (ev.a, ev.b) {
+                // Don't clack for every little bounce while the ball settles
+                (Body::Ball(ball), Body::Static(_)) if ev.impact_speed > MIN_CLACK_SPEED => {
+                    let pos = balls.get(ball).unwrap().body.c;
+                    engine.sink.play(pos, c.sounds[0].clone());
                 }
+                _ => {}
+            }
+        }
+    }));
+    schedule
+}
+
+fn entered(collisions: &[CollisionEvent]) -> impl Iterator<Item = &CollisionEvent> {
+    collisions.iter().filter(|ev| ev.phase == ContactPhase::Enter)
+}
+
+struct TitleScreen;
+
+impl State<Components> for TitleScreen {
+    fn update(&mut self, engine: &mut Engine, c: &mut Components) -> Transition<Components> {
+        //TODO: make a title page; can still press return tho
+        if engine.events.action_pressed("start") {
+            Transition::Replace(std::boxed::Box::new(GamePlay::new()))
+        } else if engine.events.action_pressed("load") {
+            if let Err(e) = load_game(engine, c) {
+                log::error!("Couldn't load the saved game: {}", e);
+            }
+            Transition::Replace(std::boxed::Box::new(GamePlay::new()))
+        } else {
+            Transition::Stay
+        }
+    }
+
+    fn render(&self, c: &Components, _ctx: &RenderContext, igs: &mut InstanceGroups) {
+        igs.render_2d(&c.menu.0, &c.menu.1, c.menu.2);
+    }
+}
 
-                // println!("make it past append");
+struct GamePlay {
+    systems: Systems,
+}
+
+impl GamePlay {
+    fn new() -> Self {
+        GamePlay { systems: systems() }
+    }
+}
+
+impl State<Components> for GamePlay {
+    fn enter(&mut self, _engine: &mut Engine, c: &mut Components) {
+        c.playing = true;
+    }
+
+    fn update(&mut self, engine: &mut Engine, c: &mut Components) -> Transition<Components> {
+        if engine.events.action_held("save"){
+            if let Err(e) = save_game(engine, c) {
+                log::error!("Couldn't save: {}", e);
+            }
+        }
+        if engine.events.action_pressed("save_scene") {
+            if let Err(e) = Scene::capture(engine, &c.world, &c.level)
+                .and_then(|scene| scene.save("scene.ron"))
+            {
+                log::error!("Couldn't save the scene: {:?}", e);
             }
-            
-            
-            _ => {}
         }
-        if events.key_released(VirtualKeyCode::Return) {
-            c.balls[0].play = false;
-            self.ball_movement.player_mag = 0.0;
-            c.physics[0].reset();
-            c.meter[1].0.w = 0.0;
-            c.meter[1].1 = 0.0;
-            c.sounds[0].1 = true;
+        if engine.events.action_pressed("screenshot") {
+            engine.save_screenshot("screenshot.png");
         }
+        // Shows up with RUST_LOG=info
+        if engine.events.action_pressed("timings") {
+            for t in self.systems.timings() {
+                log::info!("{:?} {}: {:?} (average {:?})", t.stage, t.name, t.last, t.average);
+            }
+        }
+        if engine.events.action_pressed("pause") {
+            return Transition::Push(std::boxed::Box::new(Paused::default()));
+        }
+        self.systems.run(engine, c);
+        Transition::Stay
+    }
+
+    fn render(&self, c: &Components, ctx: &RenderContext, igs: &mut InstanceGroups) {
+        let world = &c.world;
+        let models = world.storage::<ModelRef>();
+        igs.render_each_interpolated(
+            &models,
+            &world.storage::<Ball>(),
+            &world.storage(),
+            ctx.alpha,
+            Ball::to_raw_at,
+        );
+        // The goal itself is an invisible trigger inside the bin
+        igs.render_each(&models, &world.storage::<Static>(), Static::to_raw);
+
+
+        for (rect, power, mat) in c.meter.iter() {
+            igs.render_bar(&rect, *power, *mat);
+        }
+
+        for sentence in c.text.iter() {
+            sentence.draw_sentence(igs, c.text_mat);
+        }
+
+        let score_sentence = Sentence::text_to_sentence(&("Score: ".to_string() + &c.score.to_string()), [-0.2, 0.9]);
+        score_sentence.draw_sentence(igs, c.text_mat);
+    }
+}
+
+/// Freezes gameplay but leaves it on screen.  While paused the player can
+/// pick a new key for shooting.
+#[derive(Default)]
+struct Paused {
+    // waiting for the key to shoot with
+    rebinding: bool,
+    // the last key picked was already used for something else
+    refused: bool,
+}
+
+impl Paused {
+    /// Swap the shoot key for `key` and keep the change for next time
+    fn rebind_shoot(&mut self, bindings: &mut Bindings, key: Input) {
+        // Only the keyboard binding changes; the mouse still shoots
+        let old = bindings
+            .action("shoot")
+            .iter()
+            .copied()
+            .find(|i| matches!(i, Input::Key(_)));
+        if let Err(e) = bindings.rebind("shoot", old, key) {
+            log::warn!("Not rebinding shoot: {}", e);
+            self.refused = true;
+            return;
+        }
+        if let Err(e) = bindings.save(BINDINGS) {
+            log::error!("Couldn't save bindings: {:?}", e);
+        }
+    }
+}
+
+impl State<Components> for Paused {
+    fn enter(&mut self, _engine: &mut Engine, c: &mut Components) {
+        // Nothing's stepping, so stop drawing balls partway between steps
+        for (_, interp) in c.world.storage_mut::<Interpolated>().iter_mut() {
+            interp.snap(interp.current);
+        }
+    }
+
+    fn update(&mut self, engine: &mut Engine, _c: &mut Components) -> Transition<Components> {
+        if self.rebinding {
+            if let Some(key @ Input::Key(_)) = engine.events.any_pressed() {
+                self.rebind_shoot(&mut engine.events.bindings, key);
+                self.rebinding = false;
+            }
+            Transition::Stay
+        } else if engine.events.action_pressed("rebind") {
+            self.rebinding = true;
+            self.refused = false;
+            Transition::Stay
+        } else if engine.events.action_pressed("pause") {
+            Transition::Pop
+        } else {
+            Transition::Stay
+        }
+    }
+
+    fn render(&self, c: &Components, _ctx: &RenderContext, igs: &mut InstanceGroups) {
+        let (text, x) = if self.rebinding {
+            ("Press a key to shoot", -0.5)
+        } else if self.refused {
+            ("That key is taken", -0.45)
+        } else {
+            ("Paused", -0.15)
+        };
+        Sentence::text_to_sentence(text, [x, 0.0]).draw_sentence(igs, c.text_mat);
+    }
+
+    fn render_below(&self) -> bool {
+        true
     }
 }
 
 pub struct BallGame {
     components: Components,
-    systems: Systems,
+    states: StateStack<Components>,
 }
 
 impl Game for BallGame {
-    type StaticData = Components;
-    type SystemData = Systems;
     fn start(engine: &mut Engine) -> Self {
-        let components = Components::new(engine);
-        let systems = Systems::new();
+        let mut components = Components::new(engine);
+        record_or_replay(engine);
+        let mut states = StateStack::new();
+        states.push(std::boxed::Box::new(TitleScreen), engine, &mut components);
         let game = BallGame {
             components: components,
-            systems: systems,
+            states: states,
         };
         game
     }
 
     fn update(&mut self, engine: &mut Engine) {
-        match self.components.mode{
-            Mode::TitleScreen => {
-                //TODO: make a title page; can still press return tho
-                if engine.events.key_pressed(VirtualKeyCode::Return) == true {
-                    self.components.mode = Mode::GamePlay;
-                } else if engine.events.key_pressed(VirtualKeyCode::L){
-                    load_game(&mut self.components);
-                    self.components.mode = Mode::GamePlay;
-                }
-            }
-            Mode::GamePlay => {
-                if engine.events.key_held(VirtualKeyCode::P){
-                    save_game(&mut self.components);
-                   
-                }
-                self.components
-                    .camera
-                    .update(&engine.events, &mut self.components.balls[0]);
-                self.components.camera.update_camera(engine.camera_mut());
-                self.systems.process(&engine.events, &mut self.components, &engine.sink);
-            }
-            Mode::EndGame => {}
+        self.states.update(engine, &mut self.components);
+    }
+
+    fn render(&self, ctx: &RenderContext, igs: &mut InstanceGroups) {
+        self.states.render(&self.components, ctx, igs);
+    }
+
+    fn on_exit(&mut self, engine: &mut Engine) {
+        if !self.components.playing {
+            return;
+        }
+        if let Err(e) = save_game(engine, &mut self.components) {
+            log::error!("Couldn't save: {}", e);
         }
-        
-        
     }
+}
 
-    fn render(&self, igs: &mut InstanceGroups) {
-        match self.components.mode {
-            Mode::TitleScreen => {             
-                igs.render_2d(&self.components.menu.0, &self.components.menu.1, &self.components.menu.2);
-            },
-            Mode::GamePlay => {
-                for ball in self.components.balls.iter() {
-                    ball.render(self.components.models.ball_model, igs);
-                }
-        
-                for stat in self.components.statics.iter() {
-                    //I just picked the floor value that was different from the rest
-                    if stat.body.n.y == 1.0 {
-                        stat.render(self.components.models.floor_model, igs);
-                    } else {
-                        stat.render(self.components.models.wall_model, igs);
-                    }
-                }
-        
-                self.components
-                    .goal
-                    .render(self.components.models.goal_model, igs);
-        
-        
-                for (rect, power, mat) in self.components.meter.iter() {
-                    igs.render_bar(&rect, *power, mat);
-                }
-        
-                for sentence in self.components.text.iter() {
-                    sentence.draw_sentence(igs, &self.components.text_mat);
-                }
-        
-                let score_sentence = Sentence::text_to_sentence(&("Score: ".to_string() + &self.components.score.to_string()), [-0.2, 0.9]);
-                score_sentence.draw_sentence(igs, &self.components.text_mat);
-            },
-            Mode::EndGame => ()
+/// `--record FILE` saves the session's input to FILE, and `--replay FILE`
+/// plays one back
+fn record_or_replay(engine: &mut Engine) {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--record", Some(path)) => engine.start_recording(path),
+            ("--replay", Some(path)) => engine.replay(Recording::load(path).unwrap()),
+            _ => eprintln!("Usage: game [--record FILE] [--replay FILE]"),
         }
     }
 }
 
-pub fn save_game(components: &mut Components) -> std::io::Result<()>{
-let serialized = serde_json::to_string(&components.score).unwrap();
-    fs::write("saved.txt", serialized);
+/// What goes in the save file.  The random numbers are saved too, so a
+/// loaded game carries on the way it would have.
+#[derive(Serialize, Deserialize)]
+struct SavedGame {
+    score: usize,
+    rng: Rngs,
+}
+
+/// What saved.txt can hold.  Older saves are just the score.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SaveFile {
+    Game(SavedGame),
+    ScoreOnly(usize),
+}
+
+pub fn save_game(engine: &Engine, components: &mut Components) -> std::io::Result<()>{
+    let saved = SavedGame {
+        score: components.score,
+        rng: engine.rng.clone(),
+    };
+    let serialized = serde_json::to_string(&saved)?;
+    fs::write("saved.txt", serialized)?;
 
     let file = File::open("saved.txt")?;
     let mut buf_reader = BufReader::new(file);
@@ -329,14 +505,19 @@ let serialized = serde_json::to_string(&components.score).unwrap();
     buf_reader.read_to_string(&mut contents)?;
     Ok(())
 }
-pub fn load_game(components: &mut Components) -> std::io::Result<()>{
+pub fn load_game(engine: &mut Engine, components: &mut Components) -> std::io::Result<()>{
     if Path::new("saved.txt").exists(){
         let file = File::open("saved.txt")?;
         let mut buf_reader = BufReader::new(file);
         let mut contents = String::new();
         buf_reader.read_to_string(&mut contents)?;
-        let deserialized: usize = serde_json::from_str(&contents).unwrap();
-        components.score = deserialized;
+        match serde_json::from_str(&contents)? {
+            SaveFile::Game(saved) => {
+                components.score = saved.score;
+                engine.rng = saved.rng;
+            }
+            SaveFile::ScoreOnly(score) => components.score = score,
+        }
     }
     //include a message that there was not saved gamestate
     Ok(())
@@ -346,7 +527,6 @@ pub fn load_game(components: &mut Components) -> std::io::Result<()>{
 
 fn main() {
     env_logger::init();
-    let title = env!("CARGO_PKG_NAME");
-    let window = winit::window::WindowBuilder::new().with_title(title);
-    game3d_engine::run::<Components, Systems, BallGame>(window, std::path::Path::new("content"));
+    let config = EngineConfig::load("content/engine.ron").unwrap();
+    game3d_engine::run::<BallGame>(config, std::path::Path::new("content"));
 }
@@ -0,0 +1,265 @@
+use crate::geom::*;
+use std::collections::HashMap;
+
+/// Cheaply narrows down which pairs of bodies could be touching, so the
+/// exact (and more expensive) tests only run on those.
+pub trait Broadphase {
+    /// Fill `pairs` with every `(i, j)`, `i < j`, whose bounds overlap.
+    /// Pairs come out sorted so every implementation agrees on the order.
+    fn candidate_pairs(&mut self, bounds: &[Box], pairs: &mut Vec<(usize, usize)>);
+}
+
+/// Tests everything against everything.  O(n²), but the reference the
+/// other broadphases have to agree with.
+pub struct BruteForce;
+
+impl Broadphase for BruteForce {
+    fn candidate_pairs(&mut self, bounds: &[Box], pairs: &mut Vec<(usize, usize)>) {
+        pairs.clear();
+        for (ai, a) in bounds.iter().enumerate() {
+            for (bi, b) in bounds[(ai + 1)..].iter().enumerate() {
+                if overlapping_box_box(a, b) {
+                    pairs.push((ai, ai + 1 + bi));
+                }
+            }
+        }
+    }
+}
+
+/// Buckets bodies into a uniform grid of cubes `cell_size` across, and only
+/// tests bodies that share a cell.  Works best when `cell_size` is a bit
+/// bigger than a typical body.
+pub struct SpatialHash {
+    pub cell_size: f32,
+    cells: HashMap<(i32, i32, i32), Vec<usize>>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "cell_size has to be positive");
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, p: Pos3) -> (i32, i32, i32) {
+        (
+            (p.x / self.cell_size).floor() as i32,
+            (p.y / self.cell_size).floor() as i32,
+            (p.z / self.cell_size).floor() as i32,
+        )
+    }
+}
+
+impl Broadphase for SpatialHash {
+    fn candidate_pairs(&mut self, bounds: &[Box], pairs: &mut Vec<(usize, usize)>) {
+        pairs.clear();
+        // Keep the buckets around so we don't reallocate every frame
+        for bucket in self.cells.values_mut() {
+            bucket.clear();
+        }
+        for (i, b) in bounds.iter().enumerate() {
+            let lo = self.cell(b.c - b.r.to_vec());
+            let hi = self.cell(b.c + b.r.to_vec());
+            for x in lo.0..=hi.0 {
+                for y in lo.1..=hi.1 {
+                    for z in lo.2..=hi.2 {
+                        self.cells.entry((x, y, z)).or_default().push(i);
+                    }
+                }
+            }
+        }
+        for bucket in self.cells.values() {
+            for (ai, &a) in bucket.iter().enumerate() {
+                for &b in bucket[(ai + 1)..].iter() {
+                    // Sharing a cell doesn't mean the bounds overlap
+                    if overlapping_box_box(&bounds[a], &bounds[b]) {
+                        pairs.push((a, b));
+                    }
+                }
+            }
+        }
+        // Bodies spanning several cells show up once per shared cell
+        pairs.sort_unstable();
+        pairs.dedup();
+        self.cells.retain(|_, bucket| !bucket.is_empty());
+    }
+}
+
+/// Sorts bodies along one axis and sweeps across them, only testing bodies
+/// whose extents on that axis overlap.  The sort order is kept between
+/// frames, so when things don't move much re-sorting is nearly free.
+pub struct SweepAndPrune {
+    pub axis: usize,
+    order: Vec<usize>,
+    active: Vec<usize>,
+}
+
+impl SweepAndPrune {
+    /// `axis` is 0 for x, 1 for y and 2 for z.  Pick the one bodies are
+    /// most spread out along.
+    pub fn new(axis: usize) -> Self {
+        assert!(axis < 3, "axis has to be 0, 1 or 2");
+        Self {
+            axis,
+            order: vec![],
+            active: vec![],
+        }
+    }
+}
+
+impl Broadphase for SweepAndPrune {
+    fn candidate_pairs(&mut self, bounds: &[Box], pairs: &mut Vec<(usize, usize)>) {
+        pairs.clear();
+        let axis = self.axis;
+        let min = |i: usize| bounds[i].c[axis] - bounds[i].r[axis];
+        let max = |i: usize| bounds[i].c[axis] + bounds[i].r[axis];
+
+        if self.order.len() != bounds.len() {
+            self.order = (0..bounds.len()).collect();
+        }
+        // Insertion sort, since last frame's order is almost right already
+        for i in 1..self.order.len() {
+            let mut j = i;
+            while j > 0 && min(self.order[j - 1]) > min(self.order[j]) {
+                self.order.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+
+        self.active.clear();
+        for &i in self.order.iter() {
+            // Anything that ends before this one starts can't touch it or
+            // anything after it
+            self.active.retain(|&a| max(a) >= min(i));
+            for &a in self.active.iter() {
+                if overlapping_box_box(&bounds[a], &bounds[i]) {
+                    pairs.push((a.min(i), a.max(i)));
+                }
+            }
+            self.active.push(i);
+        }
+        pairs.sort_unstable();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::{CollisionDetection, CollisionFilter};
+    use crate::ecs::{Entities, Entity, Storage};
+    use crate::physics::Physics;
+    use crate::shapes::{Ball, Goal};
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    fn random_box(rng: &mut StdRng) -> Box {
+        Box {
+            c: Pos3::new(
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+            ),
+            r: Pos3::new(
+                rng.gen_range(0.1..2.0),
+                rng.gen_range(0.1..2.0),
+                rng.gen_range(0.1..2.0),
+            ),
+        }
+    }
+
+    #[test]
+    fn broadphases_agree_with_brute_force() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let mut bounds: Vec<Box> = (0..200).map(|_| random_box(&mut rng)).collect();
+        let mut others: Vec<std::boxed::Box<dyn Broadphase>> = vec![
+            std::boxed::Box::new(SpatialHash::new(2.0)),
+            std::boxed::Box::new(SweepAndPrune::new(0)),
+            std::boxed::Box::new(SweepAndPrune::new(2)),
+        ];
+        let (mut expected, mut found) = (vec![], vec![]);
+        // A few frames, so the ones that keep state between frames get
+        // tested on bodies that moved
+        for _ in 0..5 {
+            BruteForce.candidate_pairs(&bounds, &mut expected);
+            assert!(!expected.is_empty());
+            for bp in others.iter_mut() {
+                bp.candidate_pairs(&bounds, &mut found);
+                assert_eq!(found, expected);
+            }
+            for b in bounds.iter_mut() {
+                b.c += Vec3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                );
+            }
+        }
+    }
+
+    /// One frame of collision detection on `balls` using `broadphase`,
+    /// returning the contacts between balls in a fixed order
+    fn ball_contacts(broadphase: impl Broadphase + 'static, balls: &[Ball]) -> Vec<(Entity, Entity, Vec3)> {
+        let mut entities = Entities::new();
+        let mut stored = Storage::new();
+        let mut physics = Storage::new();
+        for ball in balls {
+            let e = entities.spawn();
+            stored.insert(e, *ball);
+            physics.insert(e, Physics::new());
+        }
+        let goal = Goal {
+            body: Box {
+                c: Pos3::new(100.0, 100.0, 100.0),
+                r: Pos3::new(1.0, 1.0, 1.0),
+            },
+            filter: CollisionFilter::default(),
+        };
+        let mut cd = CollisionDetection::with_broadphase(broadphase);
+        cd.update(&Storage::new(), &mut stored, &goal, &mut physics, 1.0 / 60.0);
+        let mut contacts: Vec<_> = cd.contacts().mm.iter().map(|c| (c.a, c.b, c.mtv)).collect();
+        contacts.sort_by_key(|&(a, b, _)| (a, b));
+        contacts
+    }
+
+    #[test]
+    fn collision_detection_finds_the_same_contacts_with_any_broadphase() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let balls: Vec<Ball> = (0..100)
+            .map(|_| Ball {
+                body: Sphere {
+                    c: Pos3::new(
+                        rng.gen_range(-4.0..4.0),
+                        rng.gen_range(-4.0..4.0),
+                        rng.gen_range(-4.0..4.0),
+                    ),
+                    r: 0.5,
+                },
+                orientation: Quat::one(),
+                pitch: 0.0,
+                yaw: 0.0,
+                mass: 1.0,
+                play: true,
+                filter: CollisionFilter::default(),
+            })
+            .collect();
+        let expected = ball_contacts(BruteForce, &balls);
+        assert!(!expected.is_empty());
+        assert_eq!(ball_contacts(SpatialHash::new(2.0), &balls), expected);
+        assert_eq!(ball_contacts(SweepAndPrune::new(0), &balls), expected);
+        assert_eq!(ball_contacts(SweepAndPrune::new(1), &balls), expected);
+    }
+
+    #[test]
+    #[should_panic]
+    fn spatial_hash_needs_positive_cells() {
+        SpatialHash::new(0.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn sweep_and_prune_needs_a_real_axis() {
+        SweepAndPrune::new(3);
+    }
+}
@@ -1,4 +1,6 @@
-
+use crate::config::CameraConfig;
+use crate::geom::{Pos3, Ray};
+use cgmath::prelude::*;
 
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
@@ -11,9 +13,34 @@ pub struct Camera {
 }
 
 impl Camera {
+    /// Where `EngineConfig` puts the camera by default
+    pub fn new(aspect: f32) -> Self {
+        CameraConfig::default().to_camera(aspect)
+    }
+
     pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
         let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
         let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
         proj * view
     }
+
+    /// The ray from the eye through `pixel`, in pixels from the top left of
+    /// a `viewport` that many pixels across.  It starts on the near plane.
+    pub fn screen_ray(&self, pixel: (f32, f32), viewport: (f32, f32)) -> Ray {
+        // Pixels to normalized device coordinates, where y points up
+        let x = 2.0 * pixel.0 / viewport.0 - 1.0;
+        let y = 1.0 - 2.0 * pixel.1 / viewport.1;
+        let inv = self
+            .build_view_projection_matrix()
+            .invert()
+            .expect("camera matrix can't be inverted");
+        let unproject = |z: f32| {
+            let p = inv * cgmath::Vector4::new(x, y, z, 1.0);
+            Pos3::from_homogeneous(p)
+        };
+        // This is the OpenGL projection, so depth runs from -1 to 1
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+        Ray::new(near, far - near)
+    }
 }
@@ -2,7 +2,6 @@ use crate::camera::Camera;
 use crate::events::Events;
 use crate::geom::*;
 use crate::shapes::Ball;
-use winit::event::*;
 
 pub struct CameraController {
     pub pitch: f32,
@@ -18,18 +17,10 @@ impl CameraController {
             player_pos: Pos3::new(0.0, 0.0, 0.0),
         }
     }
+    /// Aims with the "pitch" and "yaw" axes
     pub fn update(&mut self, events: &Events, player: &mut Ball) {
-        // TODO: Change the control to the arrow keys?
-        if events.key_held(VirtualKeyCode::W) {
-            self.pitch -= 0.025;
-        } else if events.key_held(VirtualKeyCode::S) {
-            self.pitch += 0.025;
-        }
-        if events.key_held(VirtualKeyCode::A) {
-            self.yaw += 0.025;
-        } else if events.key_held(VirtualKeyCode::D) {
-            self.yaw -= 0.025;
-        }
+        self.pitch += events.axis("pitch") * 0.025;
+        self.yaw += events.axis("yaw") * 0.025;
         self.pitch = self.pitch.clamp(-PI / 4.0, PI / 4.0);
         self.yaw = self.yaw.clamp(0.0, PI / 2.0);
         player.pitch = self.pitch;
@@ -1,9 +1,17 @@
 // use super::{Marble, Wall};
+use crate::broadphase::{Broadphase, SweepAndPrune};
+use crate::ecs::{join, Entity, Storage};
 use crate::geom::Vec3;
-use crate::physics::{Physics, DT};
+use crate::physics::{RigidBody as _, Physics};
 use crate::shapes::*;
 use crate::geom::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 const COEFF_R: f32 = 0.5;
+// Hits slower than this don't bounce, so resting balls settle down
+const RESTING_SPEED: f32 = 0.5;
+// How far into things ccd leaves balls
+const CCD_SKIN: f32 = 0.001;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Contact<T: Copy> {
@@ -12,17 +20,172 @@ pub struct Contact<T: Copy> {
     pub mtv: Vec3,
 }
 
-#[derive(Debug)]
-pub enum CollisionEffect {
-    Score,
-    WallCollision,
-    BallCollision,
-    None,
+/// Something that can be in a collision.  There's only ever one goal.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Body {
+    Ball(Entity),
+    Static(Entity),
+    Goal,
+}
+
+/// Which colliders can touch which.  Two colliders only interact if each
+/// one's layer is in the other's mask.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct CollisionFilter {
+    /// Bits saying what this is
+    pub layer: u32,
+    /// Bits saying what this collides with
+    pub mask: u32,
+    /// Triggers report overlaps like anything else, but never push back
+    pub trigger: bool,
 }
+
+impl CollisionFilter {
+    pub const ALL: u32 = !0;
+
+    pub fn solid(layer: u32, mask: u32) -> Self {
+        CollisionFilter {
+            layer,
+            mask,
+            trigger: false,
+        }
+    }
+    pub fn trigger(layer: u32, mask: u32) -> Self {
+        CollisionFilter {
+            layer,
+            mask,
+            trigger: true,
+        }
+    }
+    pub fn interacts(&self, other: &CollisionFilter) -> bool {
+        self.layer & other.mask != 0 && other.layer & self.mask != 0
+    }
+    /// Should a contact between these push them apart?
+    fn solid_with(&self, other: &CollisionFilter) -> bool {
+        !self.trigger && !other.trigger
+    }
+}
+
+impl Default for CollisionFilter {
+    /// Solid, on layer 1, colliding with everything
+    fn default() -> Self {
+        Self::solid(1, Self::ALL)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContactPhase {
+    /// Weren't touching last frame, are now
+    Enter,
+    /// Touching last frame and this one
+    Stay,
+    /// Touching last frame, not any more
+    Exit,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CollisionEvent {
+    pub phase: ContactPhase,
+    /// Always a ball
+    pub a: Body,
+    pub b: Body,
+    /// Unit vector pointing from `b` toward `a`.  For `Exit` events it's
+    /// the last normal we saw.
+    pub normal: Vec3,
+    /// How far `a` and `b` overlapped, 0 for `Exit` events
+    pub depth: f32,
+    /// How fast they were closing along the normal, before restitution
+    pub impact_speed: f32,
+    /// Whether either one is a trigger, so nothing got pushed
+    pub trigger: bool,
+}
+
+/// What a query hit first, and where
+#[derive(Clone, Copy, Debug)]
+pub struct QueryHit {
+    pub body: Body,
+    pub hit: RayHit,
+}
+
+/// The closest thing along `ray`, up to `max_dist` away, that `filter`
+/// lets through.  Filter out the ball doing the looking for line of sight
+/// checks.
+pub fn raycast(
+    statics: &Storage<Static>,
+    balls: &Storage<Ball>,
+    goal: &Goal,
+    ray: &Ray,
+    max_dist: f32,
+    filter: impl Fn(Body) -> bool,
+) -> Option<QueryHit> {
+    if ray.is_degenerate() || max_dist.is_nan() {
+        return None;
+    }
+    closest_hit(statics, balls, goal, filter, |shape| shape.cast_ray(ray, max_dist))
+}
+
+/// Like `raycast`, but only between the segment's ends
+pub fn segment_cast(
+    statics: &Storage<Static>,
+    balls: &Storage<Ball>,
+    goal: &Goal,
+    segment: &Segment,
+    filter: impl Fn(Body) -> bool,
+) -> Option<QueryHit> {
+    let (ray, len) = segment.to_ray();
+    raycast(statics, balls, goal, &ray, len, filter)
+}
+
+/// Where would `sphere` first bump into something moving `max_dist`
+/// along `dir`?  Good for checking where a shot would go.
+pub fn sphere_cast(
+    statics: &Storage<Static>,
+    balls: &Storage<Ball>,
+    goal: &Goal,
+    sphere: &Sphere,
+    dir: Vec3,
+    max_dist: f32,
+    filter: impl Fn(Body) -> bool,
+) -> Option<QueryHit> {
+    let ray = Ray::new(sphere.c, dir);
+    if ray.is_degenerate() || max_dist.is_nan() {
+        return None;
+    }
+    closest_hit(statics, balls, goal, filter, |shape| {
+        shape.cast_sphere(sphere, &ray, max_dist)
+    })
+}
+
+fn closest_hit(
+    statics: &Storage<Static>,
+    balls: &Storage<Ball>,
+    goal: &Goal,
+    filter: impl Fn(Body) -> bool,
+    cast: impl Fn(&dyn Shape) -> Option<RayHit>,
+) -> Option<QueryHit> {
+    let statics = statics
+        .iter()
+        .map(|(e, s)| (Body::Static(e), s as &dyn Shape));
+    let balls = balls
+        .iter()
+        .map(|(e, b)| (Body::Ball(e), &b.body as &dyn Shape));
+    let goal = std::iter::once((Body::Goal, &goal.body as &dyn Shape));
+    statics
+        .chain(balls)
+        .chain(goal)
+        .filter(|(body, _)| filter(*body))
+        .filter_map(|(body, shape)| cast(shape).map(|hit| QueryHit { body, hit }))
+        .filter(|q| !q.hit.dist.is_nan())
+        .min_by(|a, b| a.hit.dist.total_cmp(&b.hit.dist))
+}
+
 #[derive(Debug)]
 pub struct Contacts {
-    pub wm: Vec<Contact<usize>>,
-    pub mm: Vec<Contact<usize>>,
+    pub wm: Vec<Contact<Entity>>,
+    pub mm: Vec<Contact<Entity>>,
+    // goals against mobiles; only restituted if the goal isn't a trigger.
+    // There's only one goal, so b is just the ball again.
+    pub gm: Vec<Contact<Entity>>,
 }
 
 impl Contacts {
@@ -30,6 +193,7 @@ impl Contacts {
         Self {
             wm: vec![],
             mm: vec![],
+            gm: vec![],
         }
     }
     fn sort(&mut self) {
@@ -41,52 +205,130 @@ impl Contacts {
     fn clear(&mut self) {
         self.wm.clear();
         self.mm.clear();
+        self.gm.clear();
     }
 }
 
 pub struct CollisionDetection {
     contacts: Contacts,
+    // geom::Box is in scope, hence the full path
+    broadphase: std::boxed::Box<dyn Broadphase>,
+    // every ball with physics, in the order bounds and swept are in
+    ids: Vec<Entity>,
+    bounds: Vec<Box>,
+    pairs: Vec<(usize, usize)>,
+    events: Vec<CollisionEvent>,
+    // who was touching whom last frame, along what normal, and whether it
+    // was a trigger
+    touching: BTreeMap<(Body, Body), (Vec3, bool)>,
+    /// Coulomb friction coefficient at every contact
+    pub friction: f32,
+    /// How much a contact slows down spinning, relative to how hard it
+    /// pushes; this is what eventually stops a rolling ball
+    pub rolling_resistance: f32,
+    /// Continuous collision detection: sweep fast balls along their last
+    /// step so they can't pass through things in between frames
+    pub ccd: bool,
+    // where each ball started its step and how far it moved, for ccd
+    swept: Vec<(Sphere, Vec3)>,
 }
 
 impl CollisionDetection {
     pub fn new() -> Self {
+        Self::with_broadphase(SweepAndPrune::new(0))
+    }
+    pub fn with_broadphase(broadphase: impl Broadphase + 'static) -> Self {
         CollisionDetection {
             contacts: Contacts::new(),
+            broadphase: std::boxed::Box::new(broadphase),
+            ids: vec![],
+            bounds: vec![],
+            pairs: vec![],
+            events: vec![],
+            touching: BTreeMap::new(),
+            friction: 0.4,
+            rolling_resistance: 0.02,
+            ccd: false,
+            swept: vec![],
         }
     }
-    pub fn restitute(&mut self, statics: &[Static], balls: &mut [Ball], physics: &mut [Physics]) {
+    pub fn set_broadphase(&mut self, broadphase: impl Broadphase + 'static) {
+        self.broadphase = std::boxed::Box::new(broadphase);
+    }
+    pub fn contacts(&self) -> &Contacts {
+        &self.contacts
+    }
+    /// Everything that happened in the last `update`
+    pub fn events(&self) -> &[CollisionEvent] {
+        &self.events
+    }
+    pub fn restitute(
+        &mut self,
+        statics: &Storage<Static>,
+        balls: &mut Storage<Ball>,
+        goal: &Goal,
+        physics: &mut Storage<Physics>,
+        dt: f32,
+    ) {
         self.contacts.sort();
         // Lots of marbles on the floor...
 
         for c in self.contacts.wm.iter() {
-            let a = c.a;
-            let b = c.b;
+            let (ball, p, wall) = match (
+                balls.get_mut(c.a),
+                physics.get_mut(c.a),
+                statics.get(c.b),
+            ) {
+                (Some(ball), Some(p), Some(wall)) => (ball, p, wall),
+                _ => continue,
+            };
+            if !ball.filter.solid_with(&wall.filter) {
+                continue;
+            }
             // Are they still touching?  This way we don't need to track disps or anything
             // at the expense of some extra collision checks
-            if let Some(disp) = disp_sphere_plane(&balls[a].body, &statics[b].body) {
-                // We can imagine we're instantaneously applying a
-                // velocity change to pop the object just above the floor.
-                // marbles[a].body.c += disp;
-                // It feels a little weird to be adding displacement (in
-                // units) to velocity (in units/frame), but we'll roll
-                // with it.  We're not exactly modeling a normal force
-                // here but it's something like that.
-                balls[a].body.c += disp;
-                physics[a].momentum += (disp * balls[a].mass * COEFF_R) * DT;
+            if let Some(disp) = wall.disp_sphere(&ball.body) {
+                self.push_off(ball, p, wall, disp, dt);
+            }
+        }
+        // A solid goal is just another box
+        let goal_static = Static {
+            body: StaticShape::Aabb(goal.body),
+            filter: goal.filter,
+        };
+        for c in self.contacts.gm.iter() {
+            let (ball, p) = match (balls.get_mut(c.a), physics.get_mut(c.a)) {
+                (Some(ball), Some(p)) => (ball, p),
+                _ => continue,
+            };
+            if !ball.filter.solid_with(&goal.filter) {
+                continue;
+            }
+            if let Some(disp) = goal_static.disp_sphere(&ball.body) {
+                self.push_off(ball, p, &goal_static, disp, dt);
             }
         }
         // That can bump into each other in perfectly elastic collisions!
         for c in self.contacts.mm.iter() {
-            let a = c.a;
-            let b = c.b;
+            let (ba, bb) = match balls.get2_mut(c.a, c.b) {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let (pa, pb) = match physics.get2_mut(c.a, c.b) {
+                Some(pair) => pair,
+                None => continue,
+            };
+            if !ba.filter.solid_with(&bb.filter) {
+                continue;
+            }
             // Just split the difference.  In crowded situations this will
             // cause issues, but those will always be hard to solve with
             // this kind of technique.
-            if let Some(disp) = disp_sphere_sphere(&balls[a].body, &balls[b].body) {
-                let m1 = balls[a].mass;
-                let v1 = physics[a].momentum / m1;
-                let m2 = balls[b].mass;
-                let v2 = physics[b].momentum / m2;
+            if let Some(disp) = disp_sphere_sphere(&ba.body, &bb.body) {
+                let m1 = ba.mass;
+                let v1 = pa.momentum / m1;
+                let m2 = bb.mass;
+                let v2 = pb.momentum / m2;
 
                 let v1f = (m1 * v1 + 2.0 * m2 * v2 - m2 * v1) / (m1 + m2);
                 let v2f = v1 + v1f - v2;
@@ -94,69 +336,753 @@ impl CollisionDetection {
                 let v1r = v1f - v1;
                 let v2r = v2f - v2;
 
-                balls[a].body.c -= disp / 2.0;
-                physics[a].apply_impulse(COEFF_R * m1 * v1r);
-                balls[b].body.c += disp / 2.0;
-                physics[b].apply_impulse(COEFF_R * m2 * v2r);
+                ba.body.c -= disp / 2.0;
+                pa.apply_impulse(COEFF_R * m1 * v1r);
+                bb.body.c += disp / 2.0;
+                pb.apply_impulse(COEFF_R * m2 * v2r);
+
+                // From b toward a.  disp is zero if they're only just
+                // touching, like after ccd, so go by the centers.
+                let offset = ba.body.c - bb.body.c;
+                if offset.magnitude2() == 0.0 {
+                    continue;
+                }
+                let n = offset.normalize();
+                let jn = (COEFF_R * m1 * v1r).dot(n).abs();
+                self.friction_dynamic((&*ba, pa), (&*bb, pb), n, jn);
             }
         }
     }
 
+    /// Move `ball` out of `wall` by `disp` and stop it moving into it.
+    fn push_off(&self, ball: &mut Ball, p: &mut Physics, wall: &Static, disp: Vec3, dt: f32) {
+        // We can imagine we're instantaneously applying a
+        // velocity change to pop the object just above the floor.
+        // marbles[a].body.c += disp;
+        // It feels a little weird to be adding displacement (in
+        // units) to velocity (in units/frame), but we'll roll
+        // with it.  We're not exactly modeling a normal force
+        // here but it's something like that.
+        ball.body.c += disp;
+        p.momentum += (disp * ball.mass * COEFF_R) * dt;
+
+        // Stop the ball moving into the wall, bouncing it back out
+        // if it hit hard enough.  Friction needs to know how hard
+        // the wall pushed back.
+        let n = wall.normal_at(ball.body.c);
+        let m = ball.mass;
+        let vn = p.momentum.dot(n) / m;
+        let mut jn = 0.0;
+        if vn < 0.0 {
+            let e = if -vn > RESTING_SPEED { COEFF_R } else { 0.0 };
+            jn = -(1.0 + e) * vn * m;
+            p.apply_impulse(n * jn);
+        }
+        self.friction_static(ball, p, n, jn);
+    }
+
+    /// Coulomb friction between a ball and something that doesn't move.
+    /// `n` points out of the wall and `jn` is how hard the wall pushed.
+    fn friction_static(&self, ball: &Ball, p: &mut Physics, n: Vec3, jn: f32) {
+        if jn <= 0.0 {
+            return;
+        }
+        let inv_m = 1.0 / ball.mass;
+        let inv_i = ball.inverse_inertia();
+        // From the center to where the ball touches the wall
+        let r = -n * ball.body.r;
+        let vc = p.momentum * inv_m + (inv_i * p.angular_momentum).cross(r);
+        let vt = vc - n * vc.dot(n);
+        let slip = vt.magnitude();
+        if slip > f32::EPSILON {
+            let t = vt / slip;
+            // Impulse along t per unit of slip it cancels, counting the
+            // spin it causes
+            let k = inv_m + t.dot((inv_i * r.cross(t)).cross(r));
+            // Enough to stop the slipping, so it rolls, unless that's more
+            // than friction can give
+            let jt = (slip / k).min(self.friction * jn);
+            p.apply_impulse_at(-t * jt, r);
+        }
+
+        let spin = p.angular_momentum.magnitude();
+        if spin > 0.0 {
+            let drop = (self.rolling_resistance * jn * ball.body.r).min(spin);
+            p.angular_momentum *= 1.0 - drop / spin;
+        }
+    }
+
+    /// Coulomb friction between balls `a` and `b`.  `n` points from b
+    /// toward a and `jn` is how hard they pushed each other apart.
+    fn friction_dynamic(
+        &self,
+        (ba, pa): (&Ball, &mut Physics),
+        (bb, pb): (&Ball, &mut Physics),
+        n: Vec3,
+        jn: f32,
+    ) {
+        if jn <= 0.0 {
+            return;
+        }
+        let (inv_ma, inv_mb) = (1.0 / ba.mass, 1.0 / bb.mass);
+        let (inv_ia, inv_ib) = (ba.inverse_inertia(), bb.inverse_inertia());
+        // From each center to the point where they touch
+        let ra = -n * ba.body.r;
+        let rb = n * bb.body.r;
+        let va = pa.momentum * inv_ma + (inv_ia * pa.angular_momentum).cross(ra);
+        let vb = pb.momentum * inv_mb + (inv_ib * pb.angular_momentum).cross(rb);
+        let vc = va - vb;
+        let vt = vc - n * vc.dot(n);
+        let slip = vt.magnitude();
+        if slip <= f32::EPSILON {
+            return;
+        }
+        let t = vt / slip;
+        let k = inv_ma
+            + inv_mb
+            + t.dot((inv_ia * ra.cross(t)).cross(ra))
+            + t.dot((inv_ib * rb.cross(t)).cross(rb));
+        let jt = (slip / k).min(self.friction * jn);
+        pa.apply_impulse_at(-t * jt, ra);
+        pb.apply_impulse_at(t * jt, rb);
+    }
+
+    /// Only balls with `Physics` collide.  `dt` has to be the step the
+    /// integrator just took.
     pub fn update(
         &mut self,
-        statics: &[Static],
-        balls: &mut [Ball],
+        statics: &Storage<Static>,
+        balls: &mut Storage<Ball>,
         goal: &Goal,
-        physics: &mut [Physics],
-    ) -> CollisionEffect {
+        physics: &mut Storage<Physics>,
+        dt: f32,
+    ) -> &[CollisionEvent] {
         self.contacts.clear();
-        let effect = self.gather_contacts(statics, balls, goal);
-        self.restitute(statics, balls, physics);
-        effect
+        self.ids.clear();
+        self.ids.extend(join(balls, physics).map(|(e, _, _)| e));
+        if self.ccd {
+            self.sweep(statics, balls, goal, physics, dt);
+        }
+        self.gather_contacts(statics, balls, goal);
+        // Impact speeds have to be measured before restitution changes them
+        self.record_events(statics, balls, goal, physics);
+        self.restitute(statics, balls, goal, physics, dt);
+        &self.events
     }
 
-    pub fn gather_contacts(
+    /// Move each fast ball back to where it first hit something during its
+    /// last step, so the usual contact tests see the hit instead of a ball
+    /// that's already gone through.  The goal doesn't stop balls, so going
+    /// through it just makes a contact.
+    fn sweep(
         &mut self,
-        statics: &[Static],
-        dynamics: &[Ball],
+        statics: &Storage<Static>,
+        balls: &mut Storage<Ball>,
         goal: &Goal,
-    ) -> CollisionEffect {
-        let mut effect = CollisionEffect::None;
-        
-        // collide mobiles against mobiles
-        for (ai, a) in dynamics.iter().enumerate() {
-            for (bi, b) in dynamics[(ai + 1)..].iter().enumerate() {
-                let bi = ai + 1 + bi;
-                if let Some(disp) = disp_sphere_sphere(&a.body, &b.body) {
-                    self.contacts.mm.push(Contact {
-                        a: ai,
-                        b: bi,
-                        mtv: disp,
-                    });
-                    effect = CollisionEffect::BallCollision;
+        physics: &Storage<Physics>,
+        dt: f32,
+    ) {
+        // The integrator moved each ball by exactly velocity * dt
+        self.swept.clear();
+        self.swept.extend(self.ids.iter().map(|&e| {
+            let (b, p) = (balls.get(e).unwrap(), physics.get(e).unwrap());
+            let motion = if b.is_simulated() {
+                p.velocity * dt
+            } else {
+                Vec3::zero()
+            };
+            let start = Sphere {
+                c: b.body.c - motion,
+                r: b.body.r,
+            };
+            (start, motion)
+        }));
+
+        for ai in 0..self.ids.len() {
+            let a = self.ids[ai];
+            let (start, va) = self.swept[ai];
+            // Slower balls can't skip past anything the discrete tests
+            // would miss
+            if va.magnitude() < start.r {
+                continue;
+            }
+            let mut toi: Option<f32> = None;
+            let mut earliest = |t: Option<f32>| {
+                if let Some(t) = t {
+                    toi = Some(toi.map_or(t, |toi: f32| toi.min(t)));
+                }
+            };
+            // Triggers don't stop anything, so they're no use here.
+            // Anything it was already touching when the step started is
+            // left to the contact tests, so balls rolling along the floor
+            // don't get stuck at the start of every step.  Everything else
+            // counts, even if it ends the step overlapping it: a ball
+            // that went through a thin quad ends up just behind it.
+            let filter = balls.get(a).unwrap().filter;
+            if filter.trigger {
+                continue;
+            }
+            let grown = Sphere {
+                c: start.c,
+                r: start.r + CCD_SKIN,
+            };
+            let goal_solid = filter.interacts(&goal.filter) && !goal.filter.trigger;
+            for (_, s) in statics.iter() {
+                if filter.interacts(&s.filter) && !s.filter.trigger && s.disp_sphere(&grown).is_none()
+                {
+                    earliest(s.sweep_sphere(&start, va));
+                }
+            }
+            for (bi, (sb, vb)) in self.swept.iter().enumerate() {
+                let other = balls.get(self.ids[bi]).unwrap();
+                if bi != ai
+                    && filter.interacts(&other.filter)
+                    && !other.filter.trigger
+                    && disp_sphere_sphere(&grown, sb).is_none()
+                {
+                    earliest(sweep_sphere_sphere(&start, va, sb, *vb));
+                }
+            }
+            if goal_solid && disp_sphere_box(&grown, &goal.body).is_none() {
+                earliest(sweep_sphere_box(&start, va, &goal.body));
+            }
+
+            // Stop a hair inside whatever it hit, so rounding can't make
+            // the contact tests below miss it
+            let t = toi.map_or(1.0, |t| (t + CCD_SKIN / va.magnitude()).min(1.0));
+            balls.get_mut(a).unwrap().body.c = start.c + va * t;
+            // Passing all the way through a trigger still counts, but
+            // overlapping it at the end gets picked up as usual
+            let end = balls.get(a).unwrap().body;
+            for (b, s) in statics.iter() {
+                if !(s.filter.trigger && filter.interacts(&s.filter)) {
+                    continue;
+                }
+                if let Some(ts) = s.sweep_sphere(&start, va) {
+                    if ts <= t && s.disp_sphere(&end).is_none() {
+                        self.contacts.wm.push(Contact {
+                            a,
+                            b,
+                            mtv: Vec3::zero(),
+                        });
+                    }
                 }
             }
+            if goal.filter.trigger && filter.interacts(&goal.filter) {
+                if let Some(tg) = sweep_sphere_box(&start, va, &goal.body) {
+                    if tg <= t && disp_sphere_box(&end, &goal.body).is_none() {
+                        self.contacts.gm.push(Contact {
+                            a,
+                            b: a,
+                            mtv: Vec3::zero(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn record_events(
+        &mut self,
+        statics: &Storage<Static>,
+        balls: &Storage<Ball>,
+        goal: &Goal,
+        physics: &Storage<Physics>,
+    ) {
+        fn direction(v: Vec3) -> Vec3 {
+            if v.magnitude2() > 0.0 {
+                v.normalize()
+            } else {
+                Vec3::zero()
+            }
         }
-        // collide mobiles against walls
-        for (bi, b) in statics.iter().enumerate() {
-            for (ai, a) in dynamics.iter().enumerate() {
-                if let Some(disp) = disp_sphere_plane(&a.body, &b.body) {
-                    self.contacts.wm.push(Contact {
-                        a: ai,
-                        b: bi,
-                        mtv: disp,
-                    });
-                    effect = CollisionEffect::WallCollision;
+        // Contacts are only ever made for balls with physics
+        let ball = |e: Entity| balls.get(e).unwrap();
+        let velocity = |e: Entity| physics.get(e).unwrap().momentum / ball(e).mass;
+
+        let trigger = |a: &CollisionFilter, b: &CollisionFilter| !a.solid_with(b);
+
+        // normal, depth, impact speed and whether it's a trigger for
+        // everything touching this frame
+        let mut touching = BTreeMap::new();
+        for c in self.contacts.wm.iter() {
+            let normal = direction(c.mtv);
+            let speed = -velocity(c.a).dot(normal);
+            touching.insert(
+                (Body::Ball(c.a), Body::Static(c.b)),
+                (
+                    normal,
+                    c.mtv.magnitude(),
+                    speed.max(0.0),
+                    trigger(&ball(c.a).filter, &statics.get(c.b).unwrap().filter),
+                ),
+            );
+        }
+        for c in self.contacts.mm.iter() {
+            // mtv points from a to b, since it's how far to push b
+            let normal = direction(-c.mtv);
+            let speed = -(velocity(c.a) - velocity(c.b)).dot(normal);
+            touching.insert(
+                (Body::Ball(c.a), Body::Ball(c.b)),
+                (
+                    normal,
+                    c.mtv.magnitude(),
+                    speed.max(0.0),
+                    trigger(&ball(c.a).filter, &ball(c.b).filter),
+                ),
+            );
+        }
+        for c in self.contacts.gm.iter() {
+            let normal = direction(c.mtv);
+            let speed = -velocity(c.a).dot(normal);
+            touching.insert(
+                (Body::Ball(c.a), Body::Goal),
+                (
+                    normal,
+                    c.mtv.magnitude(),
+                    speed.max(0.0),
+                    trigger(&ball(c.a).filter, &goal.filter),
+                ),
+            );
+        }
+
+        self.events.clear();
+        for (&(a, b), &(normal, depth, impact_speed, trigger)) in touching.iter() {
+            let phase = if self.touching.contains_key(&(a, b)) {
+                ContactPhase::Stay
+            } else {
+                ContactPhase::Enter
+            };
+            self.events.push(CollisionEvent {
+                phase,
+                a,
+                b,
+                normal,
+                depth,
+                impact_speed,
+                trigger,
+            });
+        }
+        for (&(a, b), &(normal, trigger)) in self.touching.iter() {
+            if !touching.contains_key(&(a, b)) {
+                self.events.push(CollisionEvent {
+                    phase: ContactPhase::Exit,
+                    a,
+                    b,
+                    normal,
+                    depth: 0.0,
+                    impact_speed: 0.0,
+                    trigger,
+                });
+            }
+        }
+        self.touching = touching
+            .into_iter()
+            .map(|(k, (normal, _, _, trigger))| (k, (normal, trigger)))
+            .collect();
+    }
+
+    /// Every contact between balls in `self.ids` and anything else
+    pub fn gather_contacts(&mut self, statics: &Storage<Static>, dynamics: &Storage<Ball>, goal: &Goal) {
+        let ball = |e: Entity| dynamics.get(e).unwrap();
+
+        // collide mobiles against mobiles, but only the pairs the
+        // broadphase says might be touching
+        self.bounds.clear();
+        self.bounds.extend(self.ids.iter().map(|&e| ball(e).body.bounds()));
+        self.broadphase.candidate_pairs(&self.bounds, &mut self.pairs);
+        for &(ai, bi) in self.pairs.iter() {
+            let (a, b) = (self.ids[ai], self.ids[bi]);
+            if !ball(a).filter.interacts(&ball(b).filter) {
+                continue;
+            }
+            if let Some(disp) = disp_sphere_sphere(&ball(a).body, &ball(b).body) {
+                self.contacts.mm.push(Contact { a, b, mtv: disp });
+            }
+        }
+        // collide mobiles against walls.  There are only ever a few walls,
+        // so checking their bounds is plenty.
+        for (b, wall) in statics.iter() {
+            let wall_bounds = wall.bounds();
+            for (ai, &a) in self.ids.iter().enumerate() {
+                if !ball(a).filter.interacts(&wall.filter)
+                    || !overlapping_box_box(&self.bounds[ai], &wall_bounds)
+                {
+                    continue;
+                }
+                if let Some(disp) = wall.disp_sphere(&ball(a).body) {
+                    self.contacts.wm.push(Contact { a, b, mtv: disp });
                 }
             }
         }
 
-        for a in dynamics.iter() {
-            if touching_sphere_box(&a.body, &goal.body) {
-                effect = CollisionEffect::Score;
+        for &a in self.ids.iter() {
+            if !ball(a).filter.interacts(&goal.filter) {
+                continue;
+            }
+            if let Some(disp) = disp_sphere_box(&ball(a).body, &goal.body) {
+                self.contacts.gm.push(Contact {
+                    a,
+                    b: a,
+                    mtv: disp,
+                });
             }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::Entities;
+    use crate::physics::Integrator;
+    use cgmath::Rad;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    /// Everything `CollisionDetection::update` looks at, with the goal
+    /// parked well out of the way
+    struct Setup {
+        entities: Entities,
+        statics: Storage<Static>,
+        balls: Storage<Ball>,
+        physics: Storage<Physics>,
+        goal: Goal,
+    }
+
+    impl Setup {
+        fn new() -> Self {
+            Setup {
+                entities: Entities::new(),
+                statics: Storage::new(),
+                balls: Storage::new(),
+                physics: Storage::new(),
+                goal: Goal {
+                    body: Box {
+                        c: Pos3::new(100.0, 100.0, 100.0),
+                        r: Pos3::new(1.0, 1.0, 1.0),
+                    },
+                    filter: CollisionFilter::trigger(1, CollisionFilter::ALL),
+                },
+            }
+        }
+
+        fn add_static(&mut self, s: Static) -> Entity {
+            let e = self.entities.spawn();
+            self.statics.insert(e, s);
+            e
+        }
+
+        /// A unit mass ball of radius 0.5 at `c`, moving at `v`
+        fn add_ball(&mut self, c: Pos3, v: Vec3) -> Entity {
+            let e = self.entities.spawn();
+            self.balls.insert(
+                e,
+                Ball {
+                    body: Sphere { c, r: 0.5 },
+                    orientation: Quat::one(),
+                    pitch: 0.0,
+                    yaw: 0.0,
+                    mass: 1.0,
+                    play: true,
+                    filter: CollisionFilter::default(),
+                },
+            );
+            let mut p = Physics::new();
+            p.velocity = v;
+            p.momentum = v;
+            self.physics.insert(e, p);
+            e
+        }
+
+        fn update(&mut self, cd: &mut CollisionDetection) -> Vec<CollisionEvent> {
+            cd.update(
+                &self.statics,
+                &mut self.balls,
+                &self.goal,
+                &mut self.physics,
+                DT,
+            )
+            .to_vec()
+        }
+
+        fn ball(&self, e: Entity) -> &Ball {
+            self.balls.get(e).unwrap()
+        }
+    }
+
+    fn floor() -> Static {
+        Static::quad(Pos3::origin(), Quat::one(), [5.0, 5.0])
+    }
+
+    /// A ball starting 1 above a thin floor that the integrator has just
+    /// moved to 0.4 below it, so it ends the step touching the back face
+    fn ball_through_floor(setup: &mut Setup) -> Entity {
+        let v = Vec3::new(0.0, -1.4 / DT, 0.0);
+        setup.add_ball(Pos3::new(0.0, -0.4, 0.0), v)
+    }
+
+    #[test]
+    fn ccd_stops_ball_crossing_thin_quad() {
+        let mut setup = Setup::new();
+        setup.add_static(floor());
+        let ball = ball_through_floor(&mut setup);
+        let mut cd = CollisionDetection::new();
+        cd.ccd = true;
+        setup.update(&mut cd);
+        let c = setup.ball(ball).body.c;
+        assert!(c.y > 0.49, "ended up at {:?}", c);
+        assert!(setup.physics.get(ball).unwrap().momentum.y >= 0.0);
+    }
+
+    #[test]
+    fn without_ccd_ball_tunnels_through_thin_quad() {
+        let mut setup = Setup::new();
+        setup.add_static(floor());
+        let ball = ball_through_floor(&mut setup);
+        setup.update(&mut CollisionDetection::new());
+        assert!(setup.ball(ball).body.c.y < 0.0);
+    }
+
+    #[test]
+    fn ccd_leaves_rolling_ball_alone() {
+        // Fast along the floor and sinking into it a little, like gravity
+        // does every step
+        let mut setup = Setup::new();
+        setup.add_static(floor());
+        let v = Vec3::new(60.0, -0.5, 0.0);
+        let ball = setup.add_ball(Pos3::new(1.0, 0.5 - 0.5 * DT, 0.0), v);
+        let mut cd = CollisionDetection::new();
+        cd.ccd = true;
+        setup.update(&mut cd);
+        let c = setup.ball(ball).body.c;
+        assert!((c.x - 1.0).abs() < 1e-4, "ended up at {:?}", c);
+        assert!(c.y >= 0.5 - 1e-4, "ended up at {:?}", c);
+    }
+
+    #[test]
+    fn ccd_stops_balls_passing_through_each_other() {
+        let mut setup = Setup::new();
+        // Head on, each ends the step overlapping the other from the far
+        // side
+        let v = 1.8 / DT;
+        let a = setup.add_ball(Pos3::new(0.4, 0.0, 0.0), Vec3::new(v, 0.0, 0.0));
+        let b = setup.add_ball(Pos3::new(-0.4, 0.0, 0.0), Vec3::new(-v, 0.0, 0.0));
+        let mut cd = CollisionDetection::new();
+        cd.ccd = true;
+        setup.update(&mut cd);
+        assert!(setup.ball(a).body.c.x < setup.ball(b).body.c.x);
+    }
+
+    /// What happened between `a` and `b` in `events`
+    fn phases(events: &[CollisionEvent], a: Entity, b: Body) -> Vec<ContactPhase> {
+        events
+            .iter()
+            .filter(|ev| ev.a == Body::Ball(a) && ev.b == b)
+            .map(|ev| ev.phase)
+            .collect()
+    }
+
+    /// Put `ball` at `c`, touch `b` for two updates and then move away for
+    /// a third, checking the phases go Enter, Stay, Exit
+    fn enter_stay_exit(setup: &mut Setup, ball: Entity, b: Body, c: Pos3) -> Vec<CollisionEvent> {
+        let mut cd = CollisionDetection::new();
+        let mut all = vec![];
+        for (c, phase) in [
+            (c, ContactPhase::Enter),
+            (c, ContactPhase::Stay),
+            (Pos3::new(0.0, 50.0, 0.0), ContactPhase::Exit),
+        ] {
+            setup.balls.get_mut(ball).unwrap().body.c = c;
+            let events = setup.update(&mut cd);
+            assert_eq!(phases(&events, ball, b), vec![phase]);
+            all.extend(events);
+        }
+        // Nothing left to report
+        assert!(setup.update(&mut cd).is_empty());
+        all
+    }
+
+    #[test]
+    fn contact_enters_stays_and_exits() {
+        let mut setup = Setup::new();
+        let floor = setup.add_static(floor());
+        let ball = setup.add_ball(Pos3::origin(), Vec3::zero());
+        let c = Pos3::new(0.0, 0.4, 0.0);
+        let events = enter_stay_exit(&mut setup, ball, Body::Static(floor), c);
+        assert!(events.iter().all(|ev| !ev.trigger));
+        assert!((events[0].normal - Vec3::unit_y()).magnitude() < 1e-4);
+        assert!((events[0].depth - 0.1).abs() < 1e-4);
+        // Exits keep the last normal
+        assert_eq!(events[2].normal, events[1].normal);
+        assert_eq!(events[2].depth, 0.0);
+    }
+
+    #[test]
+    fn trigger_enters_stays_and_exits() {
+        let mut setup = Setup::new();
+        let trigger = setup.add_static(
+            Static::aabb(Pos3::origin(), Pos3::new(1.0, 1.0, 1.0))
+                .with_filter(CollisionFilter::trigger(1, CollisionFilter::ALL)),
+        );
+        let ball = setup.add_ball(Pos3::origin(), Vec3::zero());
+        let events = enter_stay_exit(&mut setup, ball, Body::Static(trigger), Pos3::origin());
+        assert!(events.iter().all(|ev| ev.trigger));
+    }
+
+    #[test]
+    fn goal_enters_stays_and_exits() {
+        let mut setup = Setup::new();
+        let ball = setup.add_ball(Pos3::origin(), Vec3::zero());
+        let c = setup.goal.body.c;
+        let events = enter_stay_exit(&mut setup, ball, Body::Goal, c);
+        assert!(events.iter().all(|ev| ev.trigger));
+    }
+
+    #[test]
+    fn filters_need_each_other() {
+        let a = CollisionFilter::solid(0b01, 0b10);
+        let b = CollisionFilter::solid(0b10, 0b01);
+        let deaf = CollisionFilter::solid(0b10, 0b00);
+        assert!(a.interacts(&b) && b.interacts(&a));
+        // a's mask wants deaf, but deaf's doesn't want a
+        assert!(!a.interacts(&deaf) && !deaf.interacts(&a));
+        assert!(!a.interacts(&a));
+        let all = CollisionFilter::default();
+        for f in [a, b, deaf, all] {
+            assert_eq!(f.interacts(&all), all.interacts(&f));
+        }
+    }
+
+    #[test]
+    fn masked_out_statics_are_ignored() {
+        let mut setup = Setup::new();
+        setup.add_static(floor().with_filter(CollisionFilter::solid(0b10, 0b10)));
+        let ball = setup.add_ball(Pos3::new(0.0, 0.4, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let events = setup.update(&mut CollisionDetection::new());
+        assert!(events.is_empty());
+        assert_eq!(setup.ball(ball).body.c.y, 0.4);
+    }
+
+    #[test]
+    fn triggers_report_without_pushing() {
+        let mut setup = Setup::new();
+        let trigger = setup.add_static(
+            floor().with_filter(CollisionFilter::trigger(1, CollisionFilter::ALL)),
+        );
+        let v = Vec3::new(0.0, -1.0, 0.0);
+        let ball = setup.add_ball(Pos3::new(0.0, 0.4, 0.0), v);
+        let events = setup.update(&mut CollisionDetection::new());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].b, Body::Static(trigger));
+        assert!(events[0].trigger);
+        assert_eq!(setup.ball(ball).body.c.y, 0.4);
+        assert_eq!(setup.physics.get(ball).unwrap().momentum, v);
+
+        // The same thing solid pushes the ball out and stops it
+        setup.statics.get_mut(trigger).unwrap().filter = CollisionFilter::default();
+        let events = setup.update(&mut CollisionDetection::new());
+        assert!(!events[0].trigger);
+        assert!(setup.ball(ball).body.c.y >= 0.5 - 1e-4);
+        assert!(setup.physics.get(ball).unwrap().momentum.y >= 0.0);
+    }
+
+    #[test]
+    fn trigger_balls_pass_through_each_other() {
+        let mut setup = Setup::new();
+        let a = setup.add_ball(Pos3::new(-0.3, 0.0, 0.0), Vec3::zero());
+        let b = setup.add_ball(Pos3::new(0.3, 0.0, 0.0), Vec3::zero());
+        setup.balls.get_mut(b).unwrap().filter = CollisionFilter::trigger(1, CollisionFilter::ALL);
+        let events = setup.update(&mut CollisionDetection::new());
+        assert_eq!(events.len(), 1);
+        assert!(events[0].trigger);
+        assert_eq!(setup.ball(a).body.c.x, -0.3);
+        assert_eq!(setup.ball(b).body.c.x, 0.3);
+    }
+
+    /// Roll a ball resting on a floor tilted `angle` radians about z for a
+    /// second, returning how far it got and how fast it was going
+    fn roll_down_slope(angle: f32) -> (f32, f32) {
+        let mut setup = Setup::new();
+        let rot = Quat::from_angle_z(Rad(angle));
+        setup.add_static(Static::quad(Pos3::origin(), rot, [50.0, 50.0]));
+        let start = Pos3::origin() + rot * Vec3::unit_y() * 0.5;
+        let ball = setup.add_ball(start, Vec3::zero());
+        let integrator = Integrator::new();
+        let mut cd = CollisionDetection::new();
+        for _ in 0..60 {
+            integrator.update(&mut setup.balls, &mut setup.physics, DT);
+            setup.update(&mut cd);
+        }
+        let moved = (setup.ball(ball).body.c - start).magnitude();
+        (moved, setup.physics.get(ball).unwrap().momentum.magnitude())
+    }
+
+    #[test]
+    fn ball_rests_on_gentle_slope() {
+        // Gentle enough that friction and rolling resistance hold it
+        let (moved, speed) = roll_down_slope(0.005);
+        assert!(moved < 1e-3, "moved {}", moved);
+        assert!(speed < 1e-3, "going {}", speed);
+    }
+
+    #[test]
+    fn ball_rolls_down_steep_slope() {
+        let (moved, speed) = roll_down_slope(0.5);
+        assert!(moved > 0.1, "moved {}", moved);
+        assert!(speed > 0.1, "going {}", speed);
+    }
+
+    #[test]
+    fn sliding_ball_slows_until_it_rolls() {
+        let mut setup = Setup::new();
+        setup.add_static(floor());
+        let ball = setup.add_ball(Pos3::new(-4.0, 0.5, 0.0), Vec3::new(3.0, 0.0, 0.0));
+        let integrator = Integrator::new();
+        let mut cd = CollisionDetection::new();
+        // How fast the bottom of the ball skids over the floor
+        let slip = |setup: &Setup| {
+            let b = setup.ball(ball);
+            let p = setup.physics.get(ball).unwrap();
+            let r = Vec3::new(0.0, -b.body.r, 0.0);
+            (p.momentum / b.mass + (b.inverse_inertia() * p.angular_momentum).cross(r)).x
+        };
+        let mut last = slip(&setup);
+        for _ in 0..10 {
+            integrator.update(&mut setup.balls, &mut setup.physics, DT);
+            setup.update(&mut cd);
+            let now = slip(&setup);
+            assert!(now < last || now.abs() < 1e-3, "slip went from {} to {}", last, now);
+            last = now;
+        }
+        let v = setup.physics.get(ball).unwrap().momentum.x;
+        assert!(v < 3.0, "still going {}", v);
+        // Rolling without slipping keeps 5/7 of a solid ball's speed
+        for _ in 0..60 {
+            integrator.update(&mut setup.balls, &mut setup.physics, DT);
+            setup.update(&mut cd);
+        }
+        // Rolling resistance keeps taking a little spin back off
+        assert!(slip(&setup).abs() < 0.01, "still slipping {}", slip(&setup));
+        let v = setup.physics.get(ball).unwrap().momentum.x;
+        assert!(v > 2.0 && v < 3.0 * 5.0 / 7.0 + 1e-3, "going {}", v);
+    }
 
-        effect
+    #[test]
+    fn friction_between_balls_passes_spin_on() {
+        // A spinning ball runs into one at rest.  The bounce leaves them
+        // moving together, so only the spin rubs where they touch.
+        let mut setup = Setup::new();
+        let a = setup.add_ball(Pos3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let b = setup.add_ball(Pos3::new(0.99, 0.0, 0.0), Vec3::zero());
+        setup.physics.get_mut(a).unwrap().angular_momentum = Vec3::new(0.0, 0.1, 0.0);
+        setup.update(&mut CollisionDetection::new());
+        let (pa, pb) = (setup.physics.get(a).unwrap(), setup.physics.get(b).unwrap());
+        assert!(pa.angular_momentum.y < 0.1, "{:?}", pa.angular_momentum);
+        // Turned the other way, like meshing gears
+        assert!(pb.angular_momentum.y < 0.0, "{:?}", pb.angular_momentum);
+        // The rub drags b along the way a's near side was turning
+        assert!(pb.momentum.z < 0.0, "{:?}", pb.momentum);
+        assert!((pa.momentum.z + pb.momentum.z).abs() < 1e-6);
     }
 }
@@ -0,0 +1,200 @@
+use crate::assets::{load_data, save_data};
+use crate::camera::Camera;
+use crate::geom::Vec3;
+use crate::input::Input;
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// How the engine runs: the window, how often the simulation steps, how
+/// frames are shown, and the rest of what used to be baked into `run`.
+/// Anything left out of a config file keeps its default.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    pub title: String,
+    /// Starting width and height of the window, in pixels
+    pub window_size: [u32; 2],
+    /// Simulation steps per second
+    pub step_rate: f32,
+    /// Most steps to run between two frames.  Past this the simulation
+    /// just falls behind, instead of each slow frame making the next one
+    /// slower.
+    pub max_catch_up_steps: usize,
+    pub present_mode: PresentMode,
+    /// Samples per pixel; 1 turns multisampling off
+    pub msaa_samples: u32,
+    /// What's behind everything, as `[r, g, b]`
+    pub clear_color: [f32; 3],
+    /// Where the camera starts, before the game moves it
+    pub camera: CameraConfig,
+    pub audio: bool,
+    /// Quits whatever the game's bindings say.  Off by default, since
+    /// games quit through the "quit" action, which players can rebind.
+    pub quit_key: Option<Input>,
+    /// Seeds the engine's random streams, so every run plays out alike.
+    /// Left out, runs are seeded from the clock.
+    pub seed: Option<u64>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            title: "game3d_engine".to_string(),
+            window_size: [1024, 768],
+            step_rate: 60.0,
+            max_catch_up_steps: 5,
+            present_mode: PresentMode::Fifo,
+            msaa_samples: 1,
+            clear_color: [0.1, 0.2, 0.3],
+            camera: CameraConfig::default(),
+            audio: true,
+            quit_key: None,
+            seed: None,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Read a config, as RON if the file ends in `.ron` and JSON otherwise
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let config: Self = load_data(path.as_ref(), "engine config")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Write the config out, in the same format `load` would pick
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        save_data(self, path, "engine config")
+    }
+
+    /// How long one simulation step is, in seconds
+    pub fn dt(&self) -> f32 {
+        1.0 / self.step_rate
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure!(self.step_rate > 0.0, "step_rate has to be positive");
+        ensure!(self.max_catch_up_steps > 0, "max_catch_up_steps has to be at least 1");
+        ensure!(
+            [1, 2, 4, 8].contains(&self.msaa_samples),
+            "msaa_samples has to be 1, 2, 4 or 8"
+        );
+        Ok(())
+    }
+}
+
+/// When finished frames get shown
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum PresentMode {
+    /// Wait for vertical blank, never tearing.  Always supported.
+    Fifo,
+    /// Wait for vertical blank, but replace a waiting frame with a newer one
+    Mailbox,
+    /// Show frames straight away, tearing and all
+    Immediate,
+}
+
+impl PresentMode {
+    pub(crate) fn to_wgpu(self) -> wgpu::PresentMode {
+        match self {
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+/// A camera's starting placement and lens.  Positions are `[x, y, z]`
+/// and `fovy` is in degrees.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            eye: [-15.0, 5.0, -15.0],
+            target: [0.0, 0.0, 0.0],
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 200.0,
+        }
+    }
+}
+
+impl CameraConfig {
+    pub fn to_camera(&self, aspect: f32) -> Camera {
+        Camera {
+            eye: self.eye.into(),
+            target: self.target.into(),
+            up: Vec3::unit_y(),
+            aspect,
+            fovy: self.fovy,
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_str(name: &str, text: &str) -> Result<EngineConfig> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, text).unwrap();
+        let config = EngineConfig::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn missing_fields_keep_defaults() {
+        let config = load_str(
+            "game3d_engine_config_test.ron",
+            "(title: \"Test\", camera: (fovy: 60.0))",
+        )
+        .unwrap();
+        let default = EngineConfig::default();
+        assert_eq!(config.title, "Test");
+        assert_eq!(config.camera.fovy, 60.0);
+        assert_eq!(config.camera.eye, default.camera.eye);
+        assert_eq!(
+            EngineConfig {
+                title: default.title.clone(),
+                camera: default.camera,
+                ..config
+            },
+            default
+        );
+        let empty = load_str("game3d_engine_config_test.json", "{}").unwrap();
+        assert_eq!(empty, default);
+    }
+
+    #[test]
+    fn validate_rejects_bad_values() {
+        assert!(EngineConfig::default().validate().is_ok());
+        for samples in [0, 3, 16] {
+            let config = EngineConfig {
+                msaa_samples: samples,
+                ..EngineConfig::default()
+            };
+            assert!(config.validate().is_err(), "{} samples", samples);
+        }
+        for rate in [0.0, -60.0, f32::NAN] {
+            let config = EngineConfig {
+                step_rate: rate,
+                ..EngineConfig::default()
+            };
+            assert!(config.validate().is_err(), "step rate {}", rate);
+        }
+        assert!(load_str("game3d_engine_config_bad.ron", "(msaa_samples: 3)").is_err());
+    }
+}
@@ -0,0 +1,451 @@
+use std::any::{Any, TypeId};
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+
+/// A handle to something in a `World`.  Once an entity is despawned its
+/// index gets reused, but with a new generation, so old handles to it
+/// stop matching anything instead of pointing at whatever took its place.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+impl Entity {
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+}
+
+/// Hands out entities and keeps track of which are still alive.
+#[derive(Clone, Debug, Default)]
+pub struct Entities {
+    // current generation of every index, and whether it's in use
+    slots: Vec<(u32, bool)>,
+    free: Vec<u32>,
+}
+
+impl Entities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.1 = true;
+                Entity {
+                    index,
+                    generation: slot.0,
+                }
+            }
+            None => {
+                self.slots.push((0, true));
+                Entity {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// Returns false if `e` was already gone
+    pub fn despawn(&mut self, e: Entity) -> bool {
+        if !self.is_alive(e) {
+            return false;
+        }
+        let slot = &mut self.slots[e.index()];
+        slot.0 += 1;
+        slot.1 = false;
+        self.free.push(e.index);
+        true
+    }
+
+    pub fn is_alive(&self, e: Entity) -> bool {
+        self.slots
+            .get(e.index())
+            .is_some_and(|&(generation, alive)| alive && generation == e.generation)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, alive))| *alive)
+            .map(|(index, &(generation, _))| Entity {
+                index: index as u32,
+                generation,
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// One kind of component, for whichever entities have one.  Components
+/// are stored by entity index, so looking one up is just indexing.
+#[derive(Clone, Debug)]
+pub struct Storage<T> {
+    slots: Vec<Option<(u32, T)>>,
+    len: usize,
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Self {
+            slots: vec![],
+            len: 0,
+        }
+    }
+}
+
+impl<T> Storage<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give `e` a component, returning the one it had before if any
+    pub fn insert(&mut self, e: Entity, component: T) -> Option<T> {
+        if self.slots.len() <= e.index() {
+            self.slots.resize_with(e.index() + 1, || None);
+        }
+        let old = self.slots[e.index()].replace((e.generation, component));
+        match old {
+            Some((generation, old)) if generation == e.generation => Some(old),
+            Some(_) => None,
+            None => {
+                self.len += 1;
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, e: Entity) -> Option<T> {
+        if !self.contains(e) {
+            return None;
+        }
+        self.len -= 1;
+        self.slots[e.index()].take().map(|(_, c)| c)
+    }
+
+    pub fn contains(&self, e: Entity) -> bool {
+        self.get(e).is_some()
+    }
+
+    pub fn get(&self, e: Entity) -> Option<&T> {
+        match self.slots.get(e.index())? {
+            Some((generation, c)) if *generation == e.generation => Some(c),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, e: Entity) -> Option<&mut T> {
+        match self.slots.get_mut(e.index())? {
+            Some((generation, c)) if *generation == e.generation => Some(c),
+            _ => None,
+        }
+    }
+
+    /// Two different entities' components at once
+    pub fn get2_mut(&mut self, a: Entity, b: Entity) -> Option<(&mut T, &mut T)> {
+        if a.index == b.index || !self.contains(a) || !self.contains(b) {
+            return None;
+        }
+        let (lo, hi) = (a.index().min(b.index()), a.index().max(b.index()));
+        let (left, right) = self.slots.split_at_mut(hi);
+        let lo_c = left[lo].as_mut().map(|(_, c)| c)?;
+        let hi_c = right[0].as_mut().map(|(_, c)| c)?;
+        if a.index() < b.index() {
+            Some((lo_c, hi_c))
+        } else {
+            Some((hi_c, lo_c))
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let (generation, c) = slot.as_ref()?;
+            Some((
+                Entity {
+                    index: index as u32,
+                    generation: *generation,
+                },
+                c,
+            ))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let (generation, c) = slot.as_mut()?;
+            Some((
+                Entity {
+                    index: index as u32,
+                    generation: *generation,
+                },
+                c,
+            ))
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Every entity with both an `A` and a `B`
+pub fn join<'a, A, B>(
+    a: &'a Storage<A>,
+    b: &'a Storage<B>,
+) -> impl Iterator<Item = (Entity, &'a A, &'a B)> {
+    a.slots
+        .iter()
+        .zip(b.slots.iter())
+        .enumerate()
+        .filter_map(|(index, slots)| match slots {
+            (Some((ga, a)), Some((gb, b))) if ga == gb => Some((
+                Entity {
+                    index: index as u32,
+                    generation: *ga,
+                },
+                a,
+                b,
+            )),
+            _ => None,
+        })
+}
+
+/// Like `join`, but both components can be changed
+pub fn join_mut<'a, A, B>(
+    a: &'a mut Storage<A>,
+    b: &'a mut Storage<B>,
+) -> impl Iterator<Item = (Entity, &'a mut A, &'a mut B)> {
+    a.slots
+        .iter_mut()
+        .zip(b.slots.iter_mut())
+        .enumerate()
+        .filter_map(|(index, slots)| match slots {
+            (Some((ga, a)), Some((gb, b))) if ga == gb => Some((
+                Entity {
+                    index: index as u32,
+                    generation: *ga,
+                },
+                a,
+                b,
+            )),
+            _ => None,
+        })
+}
+
+// So the world can clean up after despawned entities without knowing
+// what type each storage holds
+trait AnyStorage {
+    fn remove_entity(&mut self, e: Entity);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> AnyStorage for Storage<T> {
+    fn remove_entity(&mut self, e: Entity) {
+        self.remove(e);
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Entities and a storage for each kind of component they have.  Each
+/// storage is borrowed on its own, so systems can change balls and their
+/// physics at the same time while only reading the statics.
+#[derive(Default)]
+pub struct World {
+    entities: Entities,
+    storages: HashMap<TypeId, RefCell<Box<dyn AnyStorage>>>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        self.entities.spawn()
+    }
+
+    /// Remove `e` and all its components.  Returns false if it was
+    /// already gone.
+    pub fn despawn(&mut self, e: Entity) -> bool {
+        if !self.entities.despawn(e) {
+            return false;
+        }
+        for storage in self.storages.values_mut() {
+            storage.get_mut().remove_entity(e);
+        }
+        true
+    }
+
+    pub fn is_alive(&self, e: Entity) -> bool {
+        self.entities.is_alive(e)
+    }
+
+    pub fn entities(&self) -> &Entities {
+        &self.entities
+    }
+
+    /// Make room for components of type `T`, so they can be looked up
+    /// even while nothing has one
+    pub fn register<T: 'static>(&mut self) {
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| RefCell::new(Box::new(Storage::<T>::new())));
+    }
+
+    /// Give `e` a component, returning the one it had before if any.
+    /// Does nothing if `e` has been despawned.
+    pub fn insert<T: 'static>(&mut self, e: Entity, component: T) -> Option<T> {
+        if !self.is_alive(e) {
+            return None;
+        }
+        self.register::<T>();
+        self.storage_mut::<T>().insert(e, component)
+    }
+
+    pub fn remove<T: 'static>(&mut self, e: Entity) -> Option<T> {
+        self.storages.get(&TypeId::of::<T>())?;
+        self.storage_mut::<T>().remove(e)
+    }
+
+    /// Every `T`.  Panics if `T` was never registered or inserted, or if
+    /// it's borrowed mutably somewhere else.
+    pub fn storage<T: 'static>(&self) -> Ref<'_, Storage<T>> {
+        Ref::map(self.cell::<T>().borrow(), |s| {
+            s.as_any().downcast_ref().unwrap()
+        })
+    }
+
+    /// Every `T`, to change.  Panics if `T` was never registered or
+    /// inserted, or if it's borrowed somewhere else.
+    pub fn storage_mut<T: 'static>(&self) -> RefMut<'_, Storage<T>> {
+        RefMut::map(self.cell::<T>().borrow_mut(), |s| {
+            s.as_any_mut().downcast_mut().unwrap()
+        })
+    }
+
+    fn cell<T: 'static>(&self) -> &RefCell<Box<dyn AnyStorage>> {
+        self.storages.get(&TypeId::of::<T>()).unwrap_or_else(|| {
+            panic!("No storage for {}", std::any::type_name::<T>())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_handles_stop_matching() {
+        let mut world = World::new();
+        let old = world.spawn();
+        world.insert(old, 1_u32);
+        assert!(world.despawn(old));
+        let new = world.spawn();
+        assert_eq!(new.index(), old.index());
+        assert_ne!(new, old);
+        assert!(!world.is_alive(old));
+        assert!(!world.despawn(old));
+        assert!(world.insert(old, 2_u32).is_none());
+        world.insert(new, 3_u32);
+        let storage = world.storage::<u32>();
+        assert_eq!(storage.get(old), None);
+        assert_eq!(storage.get(new), Some(&3));
+    }
+
+    #[test]
+    fn despawn_removes_every_component() {
+        let mut world = World::new();
+        let e = world.spawn();
+        let other = world.spawn();
+        world.insert(e, 1_u32);
+        world.insert(e, "e");
+        world.insert(other, 2_u32);
+        world.despawn(e);
+        assert_eq!(world.storage::<u32>().len(), 1);
+        assert!(world.storage::<&str>().is_empty());
+        assert_eq!(world.storage::<u32>().get(other), Some(&2));
+    }
+
+    #[test]
+    fn insert_over_stale_generation_keeps_len() {
+        let mut entities = Entities::new();
+        let mut storage = Storage::new();
+        let old = entities.spawn();
+        storage.insert(old, 1);
+        entities.despawn(old);
+        let new = entities.spawn();
+        // The old component was never removed, so this replaces it
+        assert_eq!(storage.insert(new, 2), None);
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.insert(new, 3), Some(2));
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.remove(old), None);
+        assert_eq!(storage.remove(new), Some(3));
+        assert!(storage.is_empty());
+    }
+
+    #[test]
+    fn get2_mut() {
+        let mut entities = Entities::new();
+        let mut storage = Storage::new();
+        let a = entities.spawn();
+        let b = entities.spawn();
+        storage.insert(a, 'a');
+        storage.insert(b, 'b');
+        assert!(storage.get2_mut(a, a).is_none());
+        assert_eq!(storage.get2_mut(a, b), Some((&mut 'a', &mut 'b')));
+        assert_eq!(storage.get2_mut(b, a), Some((&mut 'b', &mut 'a')));
+        let (x, y) = storage.get2_mut(b, a).unwrap();
+        std::mem::swap(x, y);
+        assert_eq!(storage.get(a), Some(&'b'));
+        // Same index, different generation
+        entities.despawn(b);
+        let c = entities.spawn();
+        assert!(storage.get2_mut(b, c).is_none());
+        assert!(storage.get2_mut(a, c).is_none());
+    }
+
+    #[test]
+    fn joins_skip_mismatched_generations() {
+        let mut entities = Entities::new();
+        let mut numbers = Storage::new();
+        let mut names = Storage::new();
+        let kept = entities.spawn();
+        let old = entities.spawn();
+        numbers.insert(kept, 1);
+        names.insert(kept, "kept");
+        numbers.insert(old, 2);
+        entities.despawn(old);
+        let new = entities.spawn();
+        // Same index as `old`, but only one storage has the new generation
+        names.insert(new, "new");
+        let joined: Vec<_> = join(&numbers, &names).map(|(e, n, s)| (e, *n, *s)).collect();
+        assert_eq!(joined, vec![(kept, 1, "kept")]);
+        for (_, n, _) in join_mut(&mut numbers, &mut names) {
+            *n += 10;
+        }
+        assert_eq!(numbers.get(kept), Some(&11));
+        assert_eq!(numbers.get(old), Some(&2));
+    }
+}
@@ -1,6 +1,20 @@
+use crate::input::{Bindings, Input};
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet};
 pub use winit::event::VirtualKeyCode as KeyCode;
 
+/// Everything `Events` knows about the keyboard and mouse at one step,
+/// so it can be recorded and put back later.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct InputFrame {
+    held: BTreeMap<KeyCode, usize>,
+    released: BTreeSet<KeyCode>,
+    mouse_pos: (f32, f32),
+    mouse_delta: (f32, f32),
+    mouse_buttons: Vec<Option<usize>>,
+    mouse_buttons_released: Vec<bool>,
+}
+
 #[derive(Default)]
 pub struct Events {
     // how long has each been held?
@@ -11,9 +25,34 @@ pub struct Events {
     mouse_delta: (f32, f32),
     mouse_buttons: Vec<Option<usize>>,
     mouse_buttons_released: Vec<bool>,
+    /// What the action and axis queries look at
+    pub bindings: Bindings,
 }
 
 impl Events {
+    /// The keyboard and mouse as they are right now
+    pub fn input_frame(&self) -> InputFrame {
+        InputFrame {
+            held: self.held.clone(),
+            released: self.released.clone(),
+            mouse_pos: self.mouse_pos,
+            mouse_delta: self.mouse_delta,
+            mouse_buttons: self.mouse_buttons.clone(),
+            mouse_buttons_released: self.mouse_buttons_released.clone(),
+        }
+    }
+
+    /// Act as if the keyboard and mouse were as `frame` recorded them,
+    /// whatever they're really doing
+    pub fn set_input_frame(&mut self, frame: InputFrame) {
+        self.held = frame.held;
+        self.released = frame.released;
+        self.mouse_pos = frame.mouse_pos;
+        self.mouse_delta = frame.mouse_delta;
+        self.mouse_buttons = frame.mouse_buttons;
+        self.mouse_buttons_released = frame.mouse_buttons_released;
+    }
+
     pub(crate) fn device_event(&mut self, ev: &winit::event::DeviceEvent) {
         match ev {
             winit::event::DeviceEvent::MouseMotion { delta: (x, y) } => {
@@ -118,15 +157,85 @@ impl Events {
     }
 
     pub fn mouse_pressed(&self, button: usize) -> bool {
-        self.mouse_buttons[button] == Some(0)
+        self.mouse_buttons.get(button) == Some(&Some(0))
     }
 
     pub fn mouse_held(&self, button: usize) -> bool {
-        self.mouse_buttons[button].is_some() && !self.mouse_released(button)
+        self.mouse_buttons.get(button).is_some_and(|b| b.is_some())
+            && !self.mouse_released(button)
     }
 
     pub fn mouse_released(&self, button: usize) -> bool {
-        self.mouse_buttons_released[button] && !self.mouse_pressed(button)
+        self.mouse_buttons_released.get(button) == Some(&true) && !self.mouse_pressed(button)
+    }
+
+    pub fn input_pressed(&self, input: Input) -> bool {
+        match input {
+            Input::Key(k) => self.key_pressed(k),
+            Input::Mouse(button) => self.mouse_pressed(button),
+        }
+    }
+
+    pub fn input_held(&self, input: Input) -> bool {
+        match input {
+            Input::Key(k) => self.key_held(k),
+            Input::Mouse(button) => self.mouse_held(button),
+        }
+    }
+
+    pub fn input_released(&self, input: Input) -> bool {
+        match input {
+            Input::Key(k) => self.key_released(k),
+            Input::Mouse(button) => self.mouse_released(button),
+        }
+    }
+
+    /// Was anything bound to `action` pressed this frame?  Unbound
+    /// actions are never pressed.
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.bindings.action(action).iter().any(|&i| self.input_pressed(i))
+    }
+
+    pub fn action_held(&self, action: &str) -> bool {
+        self.bindings.action(action).iter().any(|&i| self.input_held(i))
+    }
+
+    /// Was something bound to `action` let go of this frame, with nothing
+    /// else bound to it still held?
+    pub fn action_released(&self, action: &str) -> bool {
+        let inputs = self.bindings.action(action);
+        inputs.iter().any(|&i| self.input_released(i))
+            && !inputs.iter().any(|&i| self.input_held(i))
+    }
+
+    /// -1, 0 or 1, depending on which side of `axis` is held.  Holding
+    /// both sides cancels out.
+    pub fn axis(&self, axis: &str) -> f32 {
+        let binding = match self.bindings.axis(axis) {
+            Some(binding) => binding,
+            None => return 0.0,
+        };
+        let held = |inputs: &[Input]| inputs.iter().any(|&i| self.input_held(i));
+        match (held(&binding.negative), held(&binding.positive)) {
+            (true, false) => -1.0,
+            (false, true) => 1.0,
+            _ => 0.0,
+        }
+    }
+
+    /// Some key or mouse button that was pressed this frame, for asking
+    /// the player what to rebind an action to
+    pub fn any_pressed(&self) -> Option<Input> {
+        self.held
+            .iter()
+            .find(|(_, num)| **num == 0)
+            .map(|(k, _)| Input::Key(*k))
+            .or_else(|| {
+                self.mouse_buttons
+                    .iter()
+                    .position(|b| *b == Some(0))
+                    .map(Input::Mouse)
+            })
     }
 
     pub fn mouse_pos(&self) -> (f32, f32) {
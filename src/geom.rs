@@ -1,6 +1,7 @@
 pub use cgmath::prelude::*;
 pub type Vec3 = cgmath::Vector3<f32>;
 pub type Pos3 = cgmath::Point3<f32>;
+pub type Mat3 = cgmath::Matrix3<f32>;
 pub type Mat4 = cgmath::Matrix4<f32>;
 pub type Quat = cgmath::Quaternion<f32>;
 pub const PI: f32 = std::f32::consts::PI;
@@ -25,11 +26,173 @@ pub struct Rect {
     pub h: f32,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Box {
     pub c: Pos3,
     pub r: Pos3,
 }
 
+/// A flat rectangle centered on `c`.  Before `rot` it lies in the xz plane
+/// facing +y, reaching `r[0]` along x and `r[1]` along z.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quad {
+    pub c: Pos3,
+    pub rot: Quat,
+    pub r: [f32; 2],
+}
+
+/// A box that can be turned any which way.  Before `rot` it's a `Box`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Obb {
+    pub c: Pos3,
+    pub rot: Quat,
+    pub r: Vec3,
+}
+
+/// Starts at `origin` and goes on forever along `dir`, which is kept unit
+/// length so distances along the ray are real distances.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Ray {
+    pub origin: Pos3,
+    pub dir: Vec3,
+}
+
+/// The straight line from `a` to `b`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Segment {
+    pub a: Pos3,
+    pub b: Pos3,
+}
+
+/// Where a ray (or a sphere moving along one) first hit something
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RayHit {
+    /// How far along the ray
+    pub dist: f32,
+    /// The point on the surface that got hit
+    pub point: Pos3,
+    /// Unit surface normal at `point`, facing back toward the ray
+    pub normal: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Pos3, dir: Vec3) -> Self {
+        Ray {
+            origin,
+            dir: dir.normalize(),
+        }
+    }
+    pub fn at(&self, dist: f32) -> Pos3 {
+        self.origin + self.dir * dist
+    }
+    /// No usable direction, like the ray along a zero-length segment
+    pub fn is_degenerate(&self) -> bool {
+        !self.dir.magnitude2().is_normal()
+    }
+}
+
+impl Segment {
+    /// The ray along the segment and how far it goes
+    pub fn to_ray(&self) -> (Ray, f32) {
+        let v = self.b - self.a;
+        (Ray::new(self.a, v), v.magnitude())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Triangle {
+    pub a: Pos3,
+    pub b: Pos3,
+    pub c: Pos3,
+}
+
+impl Sphere {
+    /// The smallest axis-aligned box containing the sphere
+    pub fn bounds(&self) -> Box {
+        Box {
+            c: self.c,
+            r: Pos3::new(self.r, self.r, self.r),
+        }
+    }
+}
+
+impl Quad {
+    pub fn normal(&self) -> Vec3 {
+        self.rot * Vec3::unit_y()
+    }
+    /// The same rectangle as a box with no thickness
+    pub fn to_obb(&self) -> Obb {
+        Obb {
+            c: self.c,
+            rot: self.rot,
+            r: Vec3::new(self.r[0], 0.0, self.r[1]),
+        }
+    }
+    pub fn bounds(&self) -> Box {
+        self.to_obb().bounds()
+    }
+}
+
+impl Obb {
+    /// p relative to the box, as if the box weren't turned
+    fn local_point(&self, p: Pos3) -> Pos3 {
+        Pos3::from_vec(self.rot.invert() * (p - self.c))
+    }
+    fn world_point(&self, p: Pos3) -> Pos3 {
+        self.c + self.rot * p.to_vec()
+    }
+    fn local_box(&self) -> Box {
+        Box {
+            c: Pos3::origin(),
+            r: Pos3::from_vec(self.r),
+        }
+    }
+    /// The smallest axis-aligned box containing this one
+    pub fn bounds(&self) -> Box {
+        let m = Mat3::from(self.rot);
+        let mut r = Pos3::origin();
+        for i in 0..3 {
+            // Each local axis sticks out by its extent times how much it
+            // points along world axis i
+            r[i] = (0..3).map(|j| m[j][i].abs() * self.r[j]).sum();
+        }
+        Box { c: self.c, r }
+    }
+}
+
+impl Triangle {
+    /// Facing the side the corners go counterclockwise around.  Zero for
+    /// triangles with no area.
+    pub fn normal(&self) -> Vec3 {
+        let n = (self.b - self.a).cross(self.c - self.a);
+        if n.magnitude2() > 0.0 {
+            n.normalize()
+        } else {
+            Vec3::zero()
+        }
+    }
+    pub fn bounds(&self) -> Box {
+        let mut lo = self.a;
+        let mut hi = self.a;
+        for p in [self.b, self.c].iter() {
+            for i in 0..3 {
+                lo[i] = lo[i].min(p[i]);
+                hi[i] = hi[i].max(p[i]);
+            }
+        }
+        Box {
+            c: lo.midpoint(hi),
+            r: Pos3::from_vec((hi - lo) / 2.0),
+        }
+    }
+}
+
+/// Inertia tensor of a solid ball of the given mass and radius.  It's the
+/// same about every axis, so it doesn't matter how the ball is turned.
+pub fn solid_sphere_inertia(mass: f32, r: f32) -> Mat3 {
+    Mat3::identity() * (0.4 * mass * r * r)
+}
+
 /// Are s1 and s2 touching?
 #[allow(dead_code)]
 pub fn touching_sphere_sphere(s1: &Sphere, s2: &Sphere) -> bool {
@@ -74,6 +237,197 @@ pub fn disp_sphere_plane(s: &Sphere, p: &Plane) -> Option<Vec3> {
     }
 }
 
+/// If s moves by v, how far along (0 to 1) does it first touch p?  Like
+/// `disp_sphere_plane`, only the side p's normal points out of is solid.
+pub fn sweep_sphere_plane(s: &Sphere, v: Vec3, p: &Plane) -> Option<f32> {
+    let dist = s.c.dot(p.n) - p.d;
+    let approach = -v.dot(p.n);
+    if approach <= 0.0 {
+        // Not heading toward it, even if it's touching already
+        return None;
+    }
+    if dist.abs() <= s.r {
+        return Some(0.0);
+    }
+    if dist < 0.0 {
+        // Behind the plane
+        return None;
+    }
+    let t = (dist - s.r) / approach;
+    if t <= 1.0 {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// If s1 moves by v1 and s2 by v2 at the same time, how far along (0 to
+/// 1) do they first touch?
+pub fn sweep_sphere_sphere(s1: &Sphere, v1: Vec3, s2: &Sphere, v2: Vec3) -> Option<f32> {
+    // Work in s2's frame, so only s1 moves
+    let offset = s1.c - s2.c;
+    let v = v1 - v2;
+    let r = s1.r + s2.r;
+    let b = offset.dot(v);
+    let a = v.magnitude2();
+    if b >= 0.0 || a == 0.0 {
+        // Moving apart, or not moving at all, even if they're touching
+        return None;
+    }
+    let c = offset.magnitude2() - r * r;
+    if c <= 0.0 {
+        return Some(0.0);
+    }
+    // Smaller root of |offset + v t|² = r²
+    let discr = b * b - a * c;
+    if discr < 0.0 {
+        return None;
+    }
+    let t = (-b - discr.sqrt()) / a;
+    if t <= 1.0 {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// If s moves by v, how far along (0 to 1) does it first touch b?
+pub fn sweep_sphere_box(s: &Sphere, v: Vec3, b: &Box) -> Option<f32> {
+    let q = closest_point_box(s.c, b);
+    if (s.c - q).dot(v) > 0.0 {
+        // Outside and heading away, so it won't touch it for the first time
+        return None;
+    }
+    if s.c.distance2(q) <= s.r * s.r {
+        return Some(0.0);
+    }
+    // Hit the box grown by s.r first (Ericson 5.5.7).  That's only the
+    // real answer if the hit is on one of the flat faces; near an edge or
+    // corner of the box, the grown box's corners are rounded off.
+    let lo = b.c - b.r.to_vec();
+    let hi = b.c + b.r.to_vec();
+    let mut t_enter = 0.0_f32;
+    let mut t_exit = 1.0_f32;
+    for i in 0..3 {
+        let (min, max) = (lo[i] - s.r, hi[i] + s.r);
+        if v[i].abs() < f32::EPSILON {
+            if s.c[i] < min || s.c[i] > max {
+                return None;
+            }
+            continue;
+        }
+        let t1 = (min - s.c[i]) / v[i];
+        let t2 = (max - s.c[i]) / v[i];
+        t_enter = t_enter.max(t1.min(t2));
+        t_exit = t_exit.min(t1.max(t2));
+        if t_enter > t_exit {
+            return None;
+        }
+    }
+    let p = s.c + v * t_enter;
+    // Which sides of the real box p is past, and which corner that makes
+    let mut outside = 0;
+    let mut corner = Pos3::origin();
+    for i in 0..3 {
+        if p[i] < lo[i] {
+            outside += 1;
+            corner[i] = lo[i];
+        } else if p[i] > hi[i] {
+            outside += 1;
+            corner[i] = hi[i];
+        } else {
+            corner[i] = f32::NAN;
+        }
+    }
+    if outside <= 1 {
+        return Some(t_enter);
+    }
+    // In an edge or corner region: the grown box is rounded there, so
+    // sweep against the box edges that meet p's region
+    let mut best: Option<f32> = None;
+    for axis in 0..3 {
+        if outside == 2 && !corner[axis].is_nan() {
+            continue;
+        }
+        let (mut a, mut e) = (corner, corner);
+        for i in 0..3 {
+            if corner[i].is_nan() {
+                a[i] = lo[i];
+                e[i] = hi[i];
+            }
+        }
+        a[axis] = lo[axis];
+        e[axis] = hi[axis];
+        if let Some(t) = sweep_sphere_segment(s, v, a, e) {
+            best = Some(best.map_or(t, |b| b.min(t)));
+        }
+    }
+    best
+}
+
+/// If s moves by v, how far along (0 to 1) does it first touch the
+/// segment from a to b?  That's the same as its center hitting a capsule
+/// of radius s.r around the segment.
+pub fn sweep_sphere_segment(s: &Sphere, v: Vec3, a: Pos3, b: Pos3) -> Option<f32> {
+    let point = |c: Pos3| Sphere { c, r: 0.0 };
+    let mut best = sweep_sphere_sphere(s, v, &point(a), Vec3::zero());
+    if let Some(t) = sweep_sphere_sphere(s, v, &point(b), Vec3::zero()) {
+        best = Some(best.map_or(t, |b| b.min(t)));
+    }
+    // The side of the capsule: solve for when the center is s.r from the
+    // line through a and b (Ericson 5.3.7), then check that's between them
+    let d = b - a;
+    let m = s.c - a;
+    let (dd, md, nd) = (d.dot(d), m.dot(d), v.dot(d));
+    let qa = dd * v.dot(v) - nd * nd;
+    let qb = dd * m.dot(v) - nd * md;
+    let qc = dd * (m.dot(m) - s.r * s.r) - md * md;
+    if qa > f32::EPSILON && qb < 0.0 {
+        let discr = qb * qb - qa * qc;
+        if discr >= 0.0 {
+            let t = ((-qb - discr.sqrt()) / qa).max(0.0);
+            let along = md + t * nd;
+            if t <= 1.0 && along >= 0.0 && along <= dd {
+                best = Some(best.map_or(t, |b| b.min(t)));
+            }
+        }
+    }
+    best
+}
+
+/// Sweep s along v against anything we can measure the distance to, and
+/// return how far along (0 to 1) it first touches.  Works for any shape,
+/// convex or not, as long as `dist` never overestimates.
+pub fn advance_sphere(s: &Sphere, v: Vec3, dist: impl Fn(Pos3) -> f32) -> Option<f32> {
+    // Conservative advancement: the sphere can always move as far as the
+    // gap without hitting anything, so keep doing that until the gap
+    // closes or we run out of motion.
+    const TOLERANCE: f32 = 1e-4;
+    let speed = v.magnitude();
+    let mut t = 0.0;
+    for _ in 0..32 {
+        let gap = dist(s.c + v * t) - s.r;
+        if gap <= TOLERANCE {
+            return Some(t);
+        }
+        if speed == 0.0 {
+            return None;
+        }
+        t += gap / speed;
+        if t > 1.0 {
+            return None;
+        }
+    }
+    // Skimming along a surface closes the gap slowly.  Only count it as a
+    // hit if it really got there; otherwise the discrete tests will catch
+    // whatever contact there is, since it can't be closing in fast.
+    if dist(s.c + v * t) - s.r <= TOLERANCE {
+        Some(t)
+    } else {
+        None
+    }
+}
+
 pub fn sq_dist_point_box(p: Pos3, b: &Box) -> f32 {
     let mut sq_dist = 0.0;
     for i in 0..3 {
@@ -88,6 +442,125 @@ pub fn sq_dist_point_box(p: Pos3, b: &Box) -> f32 {
     sq_dist
 }
 
+/// Closest point on or in the box to p
+pub fn closest_point_box(p: Pos3, b: &Box) -> Pos3 {
+    let mut q = p;
+    for i in 0..3 {
+        q[i] = q[i].max(b.c[i] - b.r[i]).min(b.c[i] + b.r[i]);
+    }
+    q
+}
+
+/// What's the offset I'd need to push s out of b?
+pub fn disp_sphere_box(s: &Sphere, b: &Box) -> Option<Vec3> {
+    let q = closest_point_box(s.c, b);
+    let offset = s.c - q;
+    let dist = offset.magnitude();
+    if dist > s.r {
+        return None;
+    }
+    if dist > 0.0 {
+        return Some(offset * ((s.r - dist) / dist));
+    }
+    // The center is inside the box, so push out through the nearest face
+    let mut best = Vec3::zero();
+    let mut best_depth = f32::INFINITY;
+    for i in 0..3 {
+        let local = s.c[i] - b.c[i];
+        let depth = b.r[i] - local.abs() + s.r;
+        if depth < best_depth {
+            best_depth = depth;
+            best = Vec3::zero();
+            best[i] = if local < 0.0 { -depth } else { depth };
+        }
+    }
+    Some(best)
+}
+
+/// Closest point on q to p
+pub fn closest_point_quad(p: Pos3, q: &Quad) -> Pos3 {
+    closest_point_obb(p, &q.to_obb())
+}
+
+/// What's the offset I'd need to push s off of q?  Quads have two sides,
+/// so it goes whichever way s is already on.
+pub fn disp_sphere_quad(s: &Sphere, q: &Quad) -> Option<Vec3> {
+    let offset = s.c - closest_point_quad(s.c, q);
+    let dist = offset.magnitude();
+    if dist > s.r {
+        None
+    } else if dist > 0.0 {
+        Some(offset * ((s.r - dist) / dist))
+    } else {
+        // Right in the middle of it; pick the front
+        Some(q.normal() * s.r)
+    }
+}
+
+/// Closest point on or in b to p
+pub fn closest_point_obb(p: Pos3, b: &Obb) -> Pos3 {
+    b.world_point(closest_point_box(b.local_point(p), &b.local_box()))
+}
+
+/// What's the offset I'd need to push s out of b?
+pub fn disp_sphere_obb(s: &Sphere, b: &Obb) -> Option<Vec3> {
+    let local = Sphere {
+        c: b.local_point(s.c),
+        r: s.r,
+    };
+    disp_sphere_box(&local, &b.local_box()).map(|disp| b.rot * disp)
+}
+
+/// If s moves by v, how far along (0 to 1) does it first touch b?
+pub fn sweep_sphere_obb(s: &Sphere, v: Vec3, b: &Obb) -> Option<f32> {
+    let local = Sphere {
+        c: b.local_point(s.c),
+        r: s.r,
+    };
+    sweep_sphere_box(&local, b.rot.invert() * v, &b.local_box())
+}
+
+/// Closest point on t to p
+pub fn closest_point_triangle(p: Pos3, t: &Triangle) -> Pos3 {
+    // Work out which corner, edge or face region p is in, using the
+    // barycentric coordinates of its projection (Ericson 5.1.5)
+    let (a, b, c) = (t.a, t.b, t.c);
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
 pub fn touching_sphere_box(s: &Sphere, b: &Box) -> bool {
     let sq_dist = sq_dist_point_box(s.c, b);
     if sq_dist <= s.r * s.r {
@@ -96,3 +569,368 @@ pub fn touching_sphere_box(s: &Sphere, b: &Box) -> bool {
         false
     }
 }
+
+/// Do the two axis-aligned boxes overlap (or just touch)?
+pub fn overlapping_box_box(a: &Box, b: &Box) -> bool {
+    (0..3).all(|i| (a.c[i] - b.c[i]).abs() <= a.r[i] + b.r[i])
+}
+
+/// Anything rays and moving spheres can hit.  `max_dist` is how far along
+/// the ray to look; anything further away doesn't count.
+pub trait Shape {
+    fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<RayHit>;
+    /// Move s along ray, which has to start at s's center.  The hit is
+    /// where s first touches the shape; `dist` is how far s got.
+    fn cast_sphere(&self, s: &Sphere, ray: &Ray, max_dist: f32) -> Option<RayHit>;
+}
+
+impl Shape for Sphere {
+    fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        let m = ray.origin - self.c;
+        let b = m.dot(ray.dir);
+        let c = m.magnitude2() - self.r * self.r;
+        if c <= 0.0 {
+            // Starting inside
+            return Some(RayHit {
+                dist: 0.0,
+                point: ray.origin,
+                normal: -ray.dir,
+            });
+        }
+        if b > 0.0 {
+            // Outside and pointing away
+            return None;
+        }
+        let discr = b * b - c;
+        if discr < 0.0 {
+            return None;
+        }
+        let dist = -b - discr.sqrt();
+        if dist > max_dist {
+            return None;
+        }
+        let point = ray.at(dist);
+        Some(RayHit {
+            dist,
+            point,
+            normal: (point - self.c) / self.r,
+        })
+    }
+    fn cast_sphere(&self, s: &Sphere, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        // Same as a ray against a sphere with both radii
+        let grown = Sphere {
+            c: self.c,
+            r: self.r + s.r,
+        };
+        let hit = grown.cast_ray(ray, max_dist)?;
+        let normal = if hit.dist > 0.0 {
+            hit.normal
+        } else {
+            direction_or(ray.at(0.0) - self.c, -ray.dir)
+        };
+        Some(RayHit {
+            dist: hit.dist,
+            point: self.c + normal * self.r,
+            normal,
+        })
+    }
+}
+
+impl Shape for Plane {
+    // Planes are one-sided like in disp_sphere_plane: behind is solid
+    fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        let dist_to = ray.origin.dot(self.n) - self.d;
+        if dist_to <= 0.0 {
+            return Some(RayHit {
+                dist: 0.0,
+                point: ray.origin,
+                normal: self.n,
+            });
+        }
+        let approach = -ray.dir.dot(self.n);
+        if approach <= 0.0 {
+            return None;
+        }
+        let dist = dist_to / approach;
+        if dist > max_dist {
+            return None;
+        }
+        Some(RayHit {
+            dist,
+            point: ray.at(dist),
+            normal: self.n,
+        })
+    }
+    fn cast_sphere(&self, s: &Sphere, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        // Same as a ray against the plane pushed out by the radius
+        let pushed = Plane {
+            n: self.n,
+            d: self.d + s.r,
+        };
+        let hit = pushed.cast_ray(ray, max_dist)?;
+        Some(RayHit {
+            point: hit.point - self.n * s.r,
+            ..hit
+        })
+    }
+}
+
+impl Shape for Box {
+    fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        // Slab test: the ray is inside the box while it's between all three
+        // pairs of faces at once
+        let mut t_min = 0.0;
+        let mut t_max = max_dist;
+        let mut normal = -ray.dir;
+        for i in 0..3 {
+            let (lo, hi) = (self.c[i] - self.r[i], self.c[i] + self.r[i]);
+            if ray.dir[i].abs() < f32::EPSILON {
+                // Parallel to this slab, so it has to start between its faces
+                if ray.origin[i] < lo || ray.origin[i] > hi {
+                    return None;
+                }
+                continue;
+            }
+            let ood = 1.0 / ray.dir[i];
+            let mut t1 = (lo - ray.origin[i]) * ood;
+            let mut t2 = (hi - ray.origin[i]) * ood;
+            if t1 > t2 {
+                std::mem::swap(&mut t1, &mut t2);
+            }
+            // It enters through the face it's heading toward
+            let face = if ray.dir[i] > 0.0 { -1.0 } else { 1.0 };
+            if t1 > t_min {
+                t_min = t1;
+                normal = Vec3::zero();
+                normal[i] = face;
+            }
+            t_max = t_max.min(t2);
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some(RayHit {
+            dist: t_min,
+            point: ray.at(t_min),
+            normal,
+        })
+    }
+    fn cast_sphere(&self, s: &Sphere, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        let reach = sweep_reach(s, max_dist, self.c, self.r.to_vec().magnitude());
+        let t = sweep_sphere_box(s, ray.dir * reach, self)?;
+        let dist = t * reach;
+        let c = ray.at(dist);
+        let point = closest_point_box(c, self);
+        Some(RayHit {
+            dist,
+            point,
+            normal: direction_or(c - point, -ray.dir),
+        })
+    }
+}
+
+impl Shape for Obb {
+    fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        let hit = self.local_box().cast_ray(&self.ray_to_local(ray), max_dist)?;
+        Some(self.hit_from_local(hit))
+    }
+    fn cast_sphere(&self, s: &Sphere, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        let local = Sphere {
+            c: self.local_point(s.c),
+            r: s.r,
+        };
+        let hit = self
+            .local_box()
+            .cast_sphere(&local, &self.ray_to_local(ray), max_dist)?;
+        Some(self.hit_from_local(hit))
+    }
+}
+
+impl Obb {
+    fn ray_to_local(&self, ray: &Ray) -> Ray {
+        Ray {
+            origin: self.local_point(ray.origin),
+            dir: self.rot.invert() * ray.dir,
+        }
+    }
+    fn hit_from_local(&self, hit: RayHit) -> RayHit {
+        RayHit {
+            dist: hit.dist,
+            point: self.world_point(hit.point),
+            normal: self.rot * hit.normal,
+        }
+    }
+}
+
+impl Shape for Quad {
+    fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        self.to_obb().cast_ray(ray, max_dist)
+    }
+    fn cast_sphere(&self, s: &Sphere, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        self.to_obb().cast_sphere(s, ray, max_dist)
+    }
+}
+
+// Triangles are two-sided, so normals face whichever side got hit
+impl Shape for Triangle {
+    fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        // Möller-Trumbore
+        let e1 = self.b - self.a;
+        let e2 = self.c - self.a;
+        let p = ray.dir.cross(e2);
+        let det = e1.dot(p);
+        if det.abs() < f32::EPSILON {
+            // Parallel to the triangle
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = ray.origin - self.a;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(e1);
+        let v = ray.dir.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let dist = e2.dot(q) * inv_det;
+        if dist < 0.0 || dist > max_dist {
+            return None;
+        }
+        let n = self.normal();
+        Some(RayHit {
+            dist,
+            point: ray.at(dist),
+            normal: if n.dot(ray.dir) > 0.0 { -n } else { n },
+        })
+    }
+    fn cast_sphere(&self, s: &Sphere, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        let r = self.a.distance(self.b).max(self.a.distance(self.c));
+        let reach = sweep_reach(s, max_dist, self.a, r);
+        let t = advance_sphere(s, ray.dir * reach, |c| {
+            c.distance(closest_point_triangle(c, self))
+        })?;
+        let dist = t * reach;
+        let c = ray.at(dist);
+        let point = closest_point_triangle(c, self);
+        Some(RayHit {
+            dist,
+            point,
+            normal: direction_or(c - point, -ray.dir),
+        })
+    }
+}
+
+/// How far s needs to move along a ray to get past everything within r
+/// of c, or `max_dist` if that's closer.  Sweeps move s by this much
+/// instead of `max_dist` itself, which might be infinite.
+pub(crate) fn sweep_reach(s: &Sphere, max_dist: f32, c: Pos3, r: f32) -> f32 {
+    max_dist.min(s.c.distance(c) + r + s.r)
+}
+
+/// v normalized, or `fallback` if it's too short to have a direction
+pub(crate) fn direction_or(v: Vec3, fallback: Vec3) -> Vec3 {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        fallback
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Box {
+        Box {
+            c: Pos3::origin(),
+            r: Pos3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    #[test]
+    fn sphere_hits_box_face() {
+        let s = Sphere {
+            c: Pos3::new(-3.0, 0.0, 0.0),
+            r: 0.5,
+        };
+        let t = sweep_sphere_box(&s, Vec3::new(4.0, 0.0, 0.0), &unit_box()).unwrap();
+        assert!((t - 0.375).abs() < 1e-5, "{}", t);
+    }
+
+    #[test]
+    fn sphere_grazing_box_face_misses() {
+        // Sliding along just above the top face, never touching it
+        let s = Sphere {
+            c: Pos3::new(-3.0, 1.5 + 1e-3, 0.0),
+            r: 0.5,
+        };
+        let v = Vec3::new(6.0, 0.0, 0.0);
+        assert_eq!(sweep_sphere_box(&s, v, &unit_box()), None);
+        let dist = |p: Pos3| sq_dist_point_box(p, &unit_box()).sqrt();
+        assert_eq!(advance_sphere(&s, v, dist), None);
+    }
+
+    #[test]
+    fn sphere_misses_box_corner() {
+        // Inside the grown box's corner, but outside the rounded one
+        let s = Sphere {
+            c: Pos3::new(-3.0, 1.4, 1.4),
+            r: 0.5,
+        };
+        assert_eq!(
+            sweep_sphere_box(&s, Vec3::new(6.0, 0.0, 0.0), &unit_box()),
+            None
+        );
+    }
+
+    #[test]
+    fn sphere_hits_box_edge() {
+        let s = Sphere {
+            c: Pos3::new(-3.0, 1.3, 1.3),
+            r: 0.5,
+        };
+        let t = sweep_sphere_box(&s, Vec3::new(6.0, 0.0, 0.0), &unit_box()).unwrap();
+        // Touches the edge along x at y = z = 1, so it's never past x = -1
+        let p = s.c + Vec3::new(6.0, 0.0, 0.0) * t;
+        assert!((sq_dist_point_box(p, &unit_box()).sqrt() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn sphere_hits_box_corner() {
+        let d = Vec3::new(1.0, 1.0, 1.0).normalize();
+        let s = Sphere {
+            c: Pos3::new(1.0, 1.0, 1.0) + d * 3.0,
+            r: 0.5,
+        };
+        let t = sweep_sphere_box(&s, d * -4.0, &unit_box()).unwrap();
+        assert!((t - 2.5 / 4.0).abs() < 1e-5, "{}", t);
+    }
+
+    #[test]
+    fn cast_sphere_without_a_limit() {
+        let s = Sphere {
+            c: Pos3::new(-3.0, 0.0, 0.0),
+            r: 0.5,
+        };
+        let ray = Ray::new(s.c, Vec3::unit_x());
+        let hit = unit_box().cast_sphere(&s, &ray, f32::INFINITY).unwrap();
+        assert!((hit.dist - 1.5).abs() < 1e-5, "{:?}", hit);
+        let tri = Triangle {
+            a: Pos3::new(0.0, -1.0, -1.0),
+            b: Pos3::new(0.0, 1.0, -1.0),
+            c: Pos3::new(0.0, 0.0, 1.0),
+        };
+        let hit = tri.cast_sphere(&s, &ray, f32::INFINITY).unwrap();
+        assert!((hit.dist - 2.5).abs() < 1e-3, "{:?}", hit);
+    }
+
+    #[test]
+    fn zero_length_segment_is_degenerate() {
+        let p = Pos3::new(1.0, 2.0, 3.0);
+        let (ray, len) = Segment { a: p, b: p }.to_ray();
+        assert!(ray.is_degenerate());
+        assert_eq!(len, 0.0);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::assets::{load_data, save_data};
+use crate::events::KeyCode;
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Something the player can press: a key, or a mouse button numbered
+/// the way `Events` numbers them (0 left, 1 right, 2 middle).
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Input {
+    Key(KeyCode),
+    Mouse(usize),
+}
+
+/// Inputs that push an axis towards -1 and towards +1
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub struct AxisBinding {
+    #[serde(default)]
+    pub negative: Vec<Input>,
+    #[serde(default)]
+    pub positive: Vec<Input>,
+}
+
+/// Which inputs trigger each named action, like "shoot", and drive each
+/// named axis, like "yaw".  Games ask `Events` about actions and axes
+/// instead of keys, so the keys can be changed from a bindings file or
+/// while the game is running.
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub struct Bindings {
+    #[serde(default)]
+    pub actions: BTreeMap<String, Vec<Input>>,
+    #[serde(default)]
+    pub axes: BTreeMap<String, AxisBinding>,
+}
+
+impl Bindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read bindings, as RON if the file ends in `.ron` and JSON otherwise
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        load_data(path, "bindings")
+    }
+
+    /// Write the bindings out, in the same format `load` would pick
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        save_data(self, path, "bindings")
+    }
+
+    /// The inputs bound to `action`, if any
+    pub fn action(&self, action: &str) -> &[Input] {
+        self.actions.get(action).map_or(&[], |inputs| inputs.as_slice())
+    }
+
+    /// The inputs bound to `axis`, if it's bound at all
+    pub fn axis(&self, axis: &str) -> Option<&AxisBinding> {
+        self.axes.get(axis)
+    }
+
+    /// Have `input` trigger `action` too, on top of whatever already does
+    pub fn bind(&mut self, action: &str, input: Input) {
+        let inputs = self.actions.entry(action.to_string()).or_default();
+        if !inputs.contains(&input) {
+            inputs.push(input);
+        }
+    }
+
+    /// Stop `input` triggering `action`.  Returns false if it didn't.
+    pub fn unbind(&mut self, action: &str, input: Input) -> bool {
+        match self.actions.get_mut(action) {
+            Some(inputs) => {
+                let len = inputs.len();
+                inputs.retain(|i| *i != input);
+                inputs.len() != len
+            }
+            None => false,
+        }
+    }
+
+    /// Swap `old` for `new` in what triggers `action`, leaving the rest of
+    /// its inputs alone, or just add `new` if there's no `old`.  This is
+    /// what a "press a key for..." menu wants.  Fails, changing nothing,
+    /// if `new` already does something else.
+    pub fn rebind(&mut self, action: &str, old: Option<Input>, new: Input) -> Result<()> {
+        if old == Some(new) {
+            return Ok(());
+        }
+        if let Some(other) = self.actions_for(new).find(|a| *a != action) {
+            bail!("{:?} is already bound to {}", new, other);
+        }
+        if let Some((axis, _)) = self
+            .axes
+            .iter()
+            .find(|(_, b)| b.negative.contains(&new) || b.positive.contains(&new))
+        {
+            bail!("{:?} is already bound to {}", new, axis);
+        }
+        let inputs = self.actions.entry(action.to_string()).or_default();
+        match inputs.iter().position(|i| Some(*i) == old) {
+            Some(at) if !inputs.contains(&new) => inputs[at] = new,
+            Some(at) => {
+                inputs.remove(at);
+            }
+            None if !inputs.contains(&new) => inputs.push(new),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Replace what drives `axis`
+    pub fn bind_axis(&mut self, axis: &str, negative: Vec<Input>, positive: Vec<Input>) {
+        self.axes
+            .insert(axis.to_string(), AxisBinding { negative, positive });
+    }
+
+    /// Every action `input` triggers
+    pub fn actions_for(&self, input: Input) -> impl Iterator<Item = &str> {
+        self.actions
+            .iter()
+            .filter(move |(_, inputs)| inputs.contains(&input))
+            .map(|(action, _)| action.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings() -> Bindings {
+        let mut b = Bindings::new();
+        b.bind("shoot", Input::Key(KeyCode::Space));
+        b.bind("shoot", Input::Mouse(0));
+        b.bind("pause", Input::Key(KeyCode::Tab));
+        b.bind_axis("yaw", vec![Input::Key(KeyCode::D)], vec![Input::Key(KeyCode::A)]);
+        b
+    }
+
+    #[test]
+    fn rebind_replaces_only_that_input() {
+        let mut b = bindings();
+        b.rebind("shoot", Some(Input::Key(KeyCode::Space)), Input::Key(KeyCode::X))
+            .unwrap();
+        assert_eq!(b.action("shoot"), &[Input::Key(KeyCode::X), Input::Mouse(0)]);
+        assert_eq!(b.action("pause"), &[Input::Key(KeyCode::Tab)]);
+    }
+
+    #[test]
+    fn rebind_refuses_inputs_in_use() {
+        let mut b = bindings();
+        let before = b.clone();
+        let space = Some(Input::Key(KeyCode::Space));
+        assert!(b.rebind("shoot", space, Input::Key(KeyCode::Tab)).is_err());
+        assert!(b.rebind("shoot", space, Input::Key(KeyCode::A)).is_err());
+        assert_eq!(b, before);
+    }
+}
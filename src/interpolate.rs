@@ -0,0 +1,139 @@
+use crate::ecs::Storage;
+use crate::geom::*;
+use crate::physics::RigidBody;
+
+/// Where something is and which way it's facing
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub pos: Pos3,
+    pub rot: Quat,
+}
+
+impl Transform {
+    pub fn of<B: RigidBody>(body: &B) -> Self {
+        Transform {
+            pos: body.position(),
+            rot: body.orientation(),
+        }
+    }
+
+    /// `alpha` of the way from here to `other`
+    pub fn lerp(&self, other: &Transform, alpha: f32) -> Transform {
+        // q and -q are the same rotation; blend towards whichever is
+        // closer so it doesn't go the long way round
+        let to = if self.rot.dot(other.rot) < 0.0 {
+            -other.rot
+        } else {
+            other.rot
+        };
+        Transform {
+            pos: self.pos + (other.pos - self.pos) * alpha,
+            rot: self.rot.nlerp(to, alpha),
+        }
+    }
+}
+
+/// Where a body was at the end of the last two simulation steps.  Frames
+/// usually land between steps, so drawing the body partway from one to
+/// the other keeps it moving smoothly whatever the frame rate.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Interpolated {
+    pub prev: Transform,
+    pub current: Transform,
+}
+
+impl Interpolated {
+    pub fn new(t: Transform) -> Self {
+        Interpolated {
+            prev: t,
+            current: t,
+        }
+    }
+
+    /// Another step is done and the body is now at `t`
+    pub fn push(&mut self, t: Transform) {
+        self.prev = self.current;
+        self.current = t;
+    }
+
+    /// The body jumped to `t`, so don't draw it sliding over there
+    pub fn snap(&mut self, t: Transform) {
+        *self = Self::new(t);
+    }
+
+    /// Where to draw it, `alpha` of the way from the previous step to the
+    /// current one
+    pub fn at(&self, alpha: f32) -> Transform {
+        self.prev.lerp(&self.current, alpha)
+    }
+}
+
+/// Note where every body that's interpolated ended up this step.  Run it
+/// once a step, after everything that moves bodies.
+pub fn update_transforms<B: RigidBody>(bodies: &Storage<B>, interpolated: &mut Storage<Interpolated>) {
+    for (e, interp) in interpolated.iter_mut() {
+        if let Some(body) = bodies.get(e) {
+            interp.push(Transform::of(body));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, Rotation3};
+
+    fn turned(x: f32, degrees: f32) -> Transform {
+        Transform {
+            pos: Pos3::new(x, 0.0, 0.0),
+            rot: Quat::from_angle_y(Deg(degrees)),
+        }
+    }
+
+    /// Same place and same rotation, counting q and -q as equal
+    fn assert_close(a: Transform, b: Transform) {
+        assert!((a.pos - b.pos).magnitude() < 1e-5, "{:?} vs {:?}", a, b);
+        assert!(a.rot.dot(b.rot).abs() > 1.0 - 1e-5, "{:?} vs {:?}", a, b);
+    }
+
+    #[test]
+    fn lerp_goes_from_one_end_to_the_other() {
+        let (a, b) = (turned(0.0, 0.0), turned(2.0, 90.0));
+        assert_close(a.lerp(&b, 0.0), a);
+        assert_close(a.lerp(&b, 0.5), turned(1.0, 45.0));
+        assert_close(a.lerp(&b, 1.0), b);
+    }
+
+    #[test]
+    fn lerp_takes_the_short_way_round() {
+        let (a, b) = (turned(0.0, 0.0), turned(2.0, 90.0));
+        let flipped = Transform { rot: -b.rot, ..b };
+        assert_close(a.lerp(&flipped, 0.0), a);
+        assert_close(a.lerp(&flipped, 0.5), turned(1.0, 45.0));
+        assert_close(a.lerp(&flipped, 1.0), b);
+    }
+
+    #[test]
+    fn push_keeps_the_last_two_steps() {
+        let mut interp = Interpolated::new(turned(0.0, 0.0));
+        assert_close(interp.at(0.5), turned(0.0, 0.0));
+        interp.push(turned(2.0, 90.0));
+        assert_close(interp.at(0.0), turned(0.0, 0.0));
+        assert_close(interp.at(0.5), turned(1.0, 45.0));
+        assert_close(interp.at(1.0), turned(2.0, 90.0));
+        interp.push(turned(4.0, 90.0));
+        assert_eq!(interp.prev, turned(2.0, 90.0));
+        assert_close(interp.at(0.5), turned(3.0, 90.0));
+    }
+
+    #[test]
+    fn snap_stops_the_slide() {
+        let mut interp = Interpolated::new(turned(0.0, 0.0));
+        interp.push(turned(2.0, 90.0));
+        interp.snap(turned(10.0, 30.0));
+        assert_eq!(interp.prev, interp.current);
+        for alpha in [0.0, 0.5, 1.0] {
+            assert_close(interp.at(alpha), turned(10.0, 30.0));
+        }
+    }
+}
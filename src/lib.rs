@@ -1,6 +1,6 @@
 
 // use rodio;
-use std::{path::Path, rc::Rc};
+use std::path::{Path, PathBuf};
 
 use winit::{
     event::*,
@@ -14,7 +14,6 @@ use winit::{
 pub mod geom;
 pub mod model;
 pub mod texture;
-use model::Material;
 pub mod shapes;
 
 pub mod camera;
@@ -23,6 +22,14 @@ pub mod camera_control;
 
 pub mod collision;
 
+pub mod broadphase;
+
+pub mod ecs;
+
+pub mod trimesh;
+
+pub mod scene;
+
 pub mod assets;
 use assets::*;
 
@@ -35,77 +42,442 @@ use audio::*;
 pub mod events;
 use events::Events;
 
+pub mod input;
+
+pub mod replay;
+use replay::Recording;
+
+pub mod rng;
+use rng::Rngs;
+
 pub mod physics;
 
+pub mod interpolate;
+
+pub mod schedule;
+
+pub mod state;
+
+pub mod light;
+use light::{Light, LightId, Lights};
+
 
 pub mod text;
 
 pub mod music;
 
+pub mod config;
+use config::EngineConfig;
 
-pub const DT: f32 = 1.0 / 60.0;
 
 pub trait Game: Sized {
-    type StaticData;
-    type SystemData;
     fn start(engine: &mut Engine) -> Self;
     fn update(&mut self, engine: &mut Engine);
-    fn render(&self, igs: &mut InstanceGroups);
+    fn render(&self, ctx: &RenderContext, igs: &mut InstanceGroups);
+    /// The window is now `width` by `height` pixels.  The engine has
+    /// already resized the renderer and the camera's aspect ratio.
+    fn on_resize(&mut self, _engine: &mut Engine, _width: u32, _height: u32) {}
+    /// The window gained or lost keyboard focus
+    fn on_focus_changed(&mut self, _engine: &mut Engine, _focused: bool) {}
+    /// `run` is about to stop, whether the game quit or the window was
+    /// closed.  A good time to autosave.
+    fn on_exit(&mut self, _engine: &mut Engine) {}
+    /// Every window event, after the engine has seen it
+    fn on_event(&mut self, _engine: &mut Engine, _event: &WindowEvent) {}
 }
 
 pub struct Engine {
     pub frame: usize,
+    config: EngineConfig,
     pub assets: Assets,
-    render: Render,
+    // None when running headless: no window, no GPU
+    render: Option<Render>,
+    camera: camera::Camera,
+    lights: Lights,
     pub events: Events,
     pub sink: Audio,
+    pub rng: Rngs,
+    // where to save the next rendered frame, if anywhere
+    screenshot: Option<PathBuf>,
+    // directory to dump every rendered frame into, and how many we've written
+    frame_dump: Option<(PathBuf, usize)>,
+    // set once the game wants to stop
+    quitting: bool,
+    // how far the frame being drawn is between two steps
+    alpha: f32,
+    // where to save the input we're recording, and what's recorded so far
+    recording: Option<(PathBuf, Recording)>,
+    // a recording being played back in place of live input, and the
+    // next step of it to play
+    replay: Option<(Recording, usize)>,
 }
 
 impl Engine {
+    /// An engine with no window, no GPU and no audio device.  Models and
+    /// materials are only registered, never loaded, so games can still
+    /// hold on to their refs.  Its random numbers are seeded with 0, so
+    /// every headless run goes the same way.
+    pub fn headless(asset_root: &Path) -> Self {
+        Self::headless_with_config(asset_root, EngineConfig::default())
+    }
+
+    /// A headless engine that steps at the rate `config` asks for.  Its
+    /// window, present mode and audio settings are ignored.
+    pub fn headless_with_config(asset_root: &Path, config: EngineConfig) -> Self {
+        Self {
+            frame: 0,
+            assets: Assets::new(asset_root),
+            render: None,
+            camera: config.camera.to_camera(1.0),
+            lights: Lights::new(),
+            events: Events::default(),
+            sink: Audio::disabled(),
+            rng: Rngs::new(config.seed.unwrap_or(0)),
+            screenshot: None,
+            frame_dump: None,
+            quitting: false,
+            alpha: 0.0,
+            recording: None,
+            replay: None,
+            config,
+        }
+    }
+
+    /// An engine with a GPU but no window, no swap chain and no audio
+    /// device.  Frames can only be read back with `capture_frame`, which
+    /// makes this the one to use for golden-image tests.
+    pub fn offscreen(asset_root: &Path, width: u32, height: u32) -> Self {
+        use futures::executor::block_on;
+        let size = winit::dpi::PhysicalSize::new(width, height);
+        let engine = Self::headless(asset_root);
+        Self {
+            render: Some(block_on(Render::new_offscreen(size, &engine.config))),
+            camera: engine.config.camera.to_camera(width as f32 / height as f32),
+            ..engine
+        }
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.render.is_none()
+    }
+
     pub fn load_model(&mut self, model: impl AsRef<Path>) -> assets::ModelRef {
-        self.assets.load_model(
-            &self.render.device,
-            &self.render.queue,
-            &self.render.texture_layout,
-            model,
-        )
+        match &self.render {
+            Some(render) => self.assets.load_model(
+                &render.device,
+                &render.queue,
+                &render.texture_layout,
+                model,
+            ),
+            None => self.assets.model_ref_for(model),
+        }
+    }
+
+    pub fn load_material(&mut self, name: &str, tex: impl AsRef<Path>) -> assets::MaterialRef {
+        match &self.render {
+            Some(render) => self.assets.load_material(
+                &render.device,
+                &render.queue,
+                &render.texture_layout,
+                name,
+                tex,
+            ),
+            None => self.assets.material_ref_for(name),
+        }
     }
 
-    pub fn load_material(&mut self, name: &str, tex: impl AsRef<Path>) -> Rc<model::Material> {
-        Material::load(
-            name.to_string(),
-            &self.render.device,
-            &self.render.queue,
-            &self.render.texture_layout,
-            tex,
-        )
-        .unwrap()
+    pub fn camera(&self) -> &camera::Camera {
+        &self.camera
     }
 
     pub fn camera_mut(&mut self) -> &mut camera::Camera {
-        &mut self.render.camera
+        &mut self.camera
+    }
+
+    /// Size of what we're drawing into, in pixels.  Headless engines
+    /// pretend it's 1x1.
+    pub fn viewport_size(&self) -> (f32, f32) {
+        match &self.render {
+            Some(render) => (render.size.width as f32, render.size.height as f32),
+            None => (1.0, 1.0),
+        }
     }
 
+    /// The ray from the camera through the mouse cursor
+    pub fn cursor_ray(&self) -> geom::Ray {
+        self.camera
+            .screen_ray(self.events.mouse_pos(), self.viewport_size())
+    }
+
+    /// The 3D instance `game` draws under the mouse cursor, going by model
+    /// bounding boxes.
+    pub fn pick_instance<G: Game>(&self, game: &G) -> Option<InstancePick> {
+        let mut igs = InstanceGroups::new();
+        game.render(&self.render_context(), &mut igs);
+        igs.pick(&self.assets, &self.cursor_ray())
+    }
+
+    /// The collider under the mouse cursor
+    pub fn pick_collider(
+        &self,
+        statics: &ecs::Storage<shapes::Static>,
+        balls: &ecs::Storage<shapes::Ball>,
+        goal: &shapes::Goal,
+    ) -> Option<collision::QueryHit> {
+        collision::raycast(statics, balls, goal, &self.cursor_ray(), self.camera.zfar, |_| true)
+    }
+
+    pub fn add_light(&mut self, light: Light) -> LightId {
+        self.lights.add(light)
+    }
+
+    pub fn light_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.lights.get_mut(id)
+    }
+
+    /// Move a point or spot light; does nothing for directional lights.
+    pub fn move_light(&mut self, id: LightId, pos: geom::Pos3) {
+        if let Some(light) = self.lights.get_mut(id) {
+            light.set_position(pos);
+        }
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> Option<Light> {
+        self.lights.remove(id)
+    }
+
+    pub fn lights(&self) -> &Lights {
+        &self.lights
+    }
+
+    /// Ambient color, specular strength and shininess live here too.
+    pub fn lights_mut(&mut self) -> &mut Lights {
+        &mut self.lights
+    }
+
+    /// Draw `game` into an offscreen texture and read the pixels back.
+    /// Returns None when there is no GPU to draw with.
+    pub fn capture_frame<G: Game>(&mut self, game: &G) -> Option<image::RgbaImage> {
+        let render = self.render.as_mut()?;
+        Some(render.capture(game, &mut self.assets, &self.camera, &self.lights, self.alpha))
+    }
+
+    /// Save the next frame `run` draws as a PNG at `path`.
+    pub fn save_screenshot(&mut self, path: impl AsRef<Path>) {
+        self.screenshot = Some(path.as_ref().to_owned());
+    }
+
+    /// Save every frame `run` draws as `frame_NNNNNN.png` in `dir`, or stop
+    /// dumping frames if `dir` is None.
+    pub fn dump_frames(&mut self, dir: Option<impl AsRef<Path>>) {
+        self.frame_dump = dir.map(|dir| (dir.as_ref().to_owned(), 0));
+    }
+
+    fn save_captures<G: Game>(&mut self, game: &G) {
+        if self.screenshot.is_none() && self.frame_dump.is_none() {
+            return;
+        }
+        let img = match self.capture_frame(game) {
+            Some(img) => img,
+            None => return,
+        };
+        if let Some(path) = self.screenshot.take() {
+            if let Err(e) = img.save(&path) {
+                eprintln!("{:?}", e);
+            }
+        }
+        if let Some((dir, count)) = self.frame_dump.as_mut() {
+            if let Err(e) = img.save(dir.join(format!("frame_{:06}.png", count))) {
+                eprintln!("{:?}", e);
+            }
+            *count += 1;
+        }
+    }
+
+    /// Stop running once the current frame is done
+    pub fn quit(&mut self) {
+        self.quitting = true;
+    }
+
+    pub fn is_quitting(&self) -> bool {
+        self.quitting
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// How long one simulation step is, in seconds
+    pub fn dt(&self) -> f32 {
+        self.config.dt()
+    }
+
+    /// How far (0 to 1) the frame being drawn is from the last step to
+    /// the next one
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    /// What `Game::render` gets to look at
+    pub fn render_context(&self) -> RenderContext<'_> {
+        RenderContext {
+            camera: &self.camera,
+            assets: &self.assets,
+            viewport_size: self.viewport_size(),
+            alpha: self.alpha,
+        }
+    }
+
+    /// Record the input every step from now on, along with the current
+    /// bindings and random number state.  It's saved to `path` by `stop_recording`, or when `run`
+    /// exits.
+    pub fn start_recording(&mut self, path: impl AsRef<Path>) {
+        let recording = Recording::new(self.events.bindings.clone(), self.rng.clone());
+        self.recording = Some((path.as_ref().to_owned(), recording));
+    }
+
+    /// Save what's been recorded, if anything, and stop recording
+    pub fn stop_recording(&mut self) -> anyhow::Result<()> {
+        match self.recording.take() {
+            Some((path, recording)) => recording.save(path),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Feed `recording` to the game one step at a time in place of live
+    /// input, with the bindings and random numbers it was recorded with.  Live input takes
+    /// over again once it runs out.
+    pub fn replay(&mut self, recording: Recording) {
+        self.events.bindings = recording.bindings.clone();
+        self.rng = recording.rng.clone();
+        self.replay = Some((recording, 0));
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Run one fixed `dt` simulation step of `game`.  Pressing the config's
+    /// quit key, or whatever's bound to the "quit" action, quits.
+    pub fn step<G: Game>(&mut self, game: &mut G) {
+        if let Some((recording, next)) = self.replay.as_mut() {
+            let frame = recording.frames.get(*next).cloned();
+            *next += 1;
+            match frame {
+                Some(frame) => self.events.set_input_frame(frame),
+                None => {
+                    self.replay = None;
+                    self.events.set_input_frame(events::InputFrame::default());
+                }
+            }
+        }
+        if let Some((_, recording)) = self.recording.as_mut() {
+            recording.frames.push(self.events.input_frame());
+        }
+        game.update(self);
+        let quit_key = self.config.quit_key;
+        if quit_key.is_some_and(|k| self.events.input_pressed(k))
+            || self.events.action_pressed("quit")
+        {
+            self.quit();
+        }
+
+        self.events.next_frame();
+        self.frame += 1;
+    }
+
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if let Some(render) = self.render.as_mut() {
+            render.resize(new_size);
+        }
+        self.camera.aspect = new_size.width as f32 / new_size.height as f32;
+    }
 }
 
-pub fn run<C, S, G: Game<StaticData = C, SystemData = S>>(
-    window_builder: winit::window::WindowBuilder,
+/// Drive `G` for `frames` fixed steps without a window, GPU or audio device.
+/// Handy for CI smoke tests and server-side simulation.
+pub fn run_headless<G: Game>(
     asset_root: &Path,
-) {
+    frames: usize,
+) -> (Engine, G) {
+    let mut engine = Engine::headless(asset_root);
+    let mut game = G::start(&mut engine);
+    for _ in 0..frames {
+        engine.step(&mut game);
+        if engine.is_quitting() {
+            break;
+        }
+    }
+    (engine, game)
+}
+
+/// Play `recording` through `G` without a window, GPU or audio device,
+/// for as many steps as it has.  Good for regression tests: record a
+/// session once, then check the game ends up the same way every time.
+pub fn run_replay<G: Game>(
+    asset_root: &Path,
+    recording: Recording,
+) -> (Engine, G) {
+    let mut engine = Engine::headless(asset_root);
+    let mut game = G::start(&mut engine);
+    let frames = recording.len();
+    engine.replay(recording);
+    for _ in 0..frames {
+        engine.step(&mut game);
+        if engine.is_quitting() {
+            break;
+        }
+    }
+    (engine, game)
+}
+
+/// Open a window and run `G` in it until it quits, set up the way
+/// `config` says
+pub fn run<G: Game>(config: EngineConfig, asset_root: &Path) {
     use std::time::Instant;
     let mut event_loop = EventLoop::new();
-    let window = window_builder.build(&event_loop).unwrap();
+    let window = winit::window::WindowBuilder::new()
+        .with_title(&config.title)
+        .with_inner_size(winit::dpi::PhysicalSize::new(
+            config.window_size[0],
+            config.window_size[1],
+        ))
+        .build(&event_loop)
+        .unwrap();
     let assets = Assets::new(asset_root);
     use futures::executor::block_on;
-    let render = block_on(Render::new(&window));
+    let render = block_on(Render::new(&window, &config));
+    let camera = config
+        .camera
+        .to_camera(render.size.width as f32 / render.size.height as f32);
     let events = Events::default();
-    let sink = Audio::new();
+    let sink = if config.audio {
+        Audio::new()
+    } else {
+        Audio::disabled()
+    };
+    let dt = config.dt();
+    let max_catch_up_steps = config.max_catch_up_steps;
     let mut engine = Engine {
         assets,
-        render,
+        render: Some(render),
+        camera,
+        lights: Lights::new(),
         events,
         frame: 0,
-        sink
+        sink,
+        rng: config.seed.map_or_else(Rngs::from_time, Rngs::new),
+        screenshot: None,
+        frame_dump: None,
+        quitting: false,
+        alpha: 0.0,
+        recording: None,
+        replay: None,
+        config,
     };
 
     let mut game = G::start(&mut engine);
@@ -118,6 +490,12 @@ pub fn run<C, S, G: Game<StaticData = C, SystemData = S>>(
         *control_flow = ControlFlow::Poll;
         match event {
             Event::MainEventsCleared => window.request_redraw(),
+            Event::LoopDestroyed => {
+                game.on_exit(&mut engine);
+                if let Err(e) = engine.stop_recording() {
+                    eprintln!("{:?}", e);
+                }
+            }
             Event::DeviceEvent { ref event, .. } => engine.events.device_event(event),
             Event::WindowEvent {
                 ref event,
@@ -126,35 +504,33 @@ pub fn run<C, S, G: Game<StaticData = C, SystemData = S>>(
                 engine.events.window_event(event);
                 match event {
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                    WindowEvent::KeyboardInput { input, .. } => match input {
-                        KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Escape),
-                            ..
-                        } => {
-                            *control_flow = ControlFlow::Exit;
-                        }
-                        _ => {}
-                    },
                     WindowEvent::Resized(physical_size) => {
-                        engine.render.resize(*physical_size);
+                        engine.resize(*physical_size);
+                        game.on_resize(&mut engine, physical_size.width, physical_size.height);
                     }
                     WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        engine.render.resize(**new_inner_size);
+                        engine.resize(**new_inner_size);
+                        game.on_resize(&mut engine, new_inner_size.width, new_inner_size.height);
                     }
+                    WindowEvent::Focused(focused) => game.on_focus_changed(&mut engine, *focused),
                     _ => {}
                 }
+                game.on_event(&mut engine, event);
             }
             Event::RedrawRequested(_) => {
-                match engine.render.render(&game, &mut engine.assets) {
+                // Whatever time's left over is part of the way to the next step
+                engine.alpha = available_time / dt;
+                let render = engine.render.as_mut().unwrap();
+                match render.render(&game, &mut engine.assets, &engine.camera, &engine.lights, engine.alpha) {
                     Ok(_) => {}
                     // Recreate the swap_chain if lost
-                    Err(wgpu::SwapChainError::Lost) => engine.render.resize(engine.render.size),
+                    Err(wgpu::SwapChainError::Lost) => render.resize(render.size),
                     // The system is out of memory, we should probably quit
                     Err(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                     // All other errors (Outdated, Timeout) should be resolved by the next frame
                     Err(e) => eprintln!("{:?}", e),
                 }
+                engine.save_captures(&game);
                 // The renderer "produces" time...
                 available_time += since.elapsed().as_secs_f32();
                 since = Instant::now();
@@ -162,14 +538,21 @@ pub fn run<C, S, G: Game<StaticData = C, SystemData = S>>(
             _ => {}
         }
         // And the simulation "consumes" it
-        while available_time >= DT {
+        let mut steps = 0;
+        while available_time >= dt {
+            if steps == max_catch_up_steps {
+                // Give up on the rest rather than spiral
+                available_time %= dt;
+                break;
+            }
             // Eat up one frame worth of time
-            available_time -= DT;
-
-            game.update(&mut engine);
+            available_time -= dt;
 
-            engine.events.next_frame();
-            engine.frame += 1;
+            engine.step(&mut game);
+            steps += 1;
+        }
+        if engine.is_quitting() {
+            *control_flow = ControlFlow::Exit;
         }
     });
 }
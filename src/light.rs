@@ -0,0 +1,260 @@
+use crate::geom::{Mat4, Pos3, Vec3};
+use crate::render::OPENGL_TO_WGPU_MATRIX;
+use cgmath::prelude::*;
+use std::collections::BTreeMap;
+
+// Has to match MAX_LIGHTS in shader.frag
+pub const MAX_LIGHTS: usize = 8;
+
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Debug)]
+pub struct LightId(usize);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Light {
+    /// Infinitely far away, like the sun.  `dir` is the way the light travels.
+    Directional { dir: Vec3, color: Vec3 },
+    /// Shines in every direction from `pos`, fading out to nothing at `range`.
+    /// Falloff is steep, so `color` components above 1 are normal here.
+    Point { pos: Pos3, color: Vec3, range: f32 },
+    /// A point light limited to a cone around `dir`.  Angles are in radians,
+    /// full intensity inside `inner` and fading to nothing at `outer`.
+    Spot {
+        pos: Pos3,
+        dir: Vec3,
+        color: Vec3,
+        range: f32,
+        inner: f32,
+        outer: f32,
+    },
+}
+
+impl Light {
+    pub fn directional(dir: Vec3, color: Vec3) -> Self {
+        Light::Directional { dir, color }
+    }
+
+    pub fn point(pos: Pos3, color: Vec3, range: f32) -> Self {
+        Light::Point { pos, color, range }
+    }
+
+    pub fn spot(pos: Pos3, dir: Vec3, color: Vec3, range: f32, inner: f32, outer: f32) -> Self {
+        Light::Spot {
+            pos,
+            dir,
+            color,
+            range,
+            inner,
+            outer,
+        }
+    }
+
+    /// Move a point or spot light.  Directional lights have no position.
+    pub fn set_position(&mut self, p: Pos3) {
+        match self {
+            Light::Directional { .. } => {}
+            Light::Point { pos, .. } | Light::Spot { pos, .. } => *pos = p,
+        }
+    }
+
+    /// Aim a directional or spot light.  Point lights have no direction.
+    pub fn set_direction(&mut self, d: Vec3) {
+        match self {
+            Light::Point { .. } => {}
+            Light::Directional { dir, .. } | Light::Spot { dir, .. } => *dir = d,
+        }
+    }
+
+    fn to_raw(self) -> LightRaw {
+        let mut raw = LightRaw {
+            position: [0.0; 3],
+            kind: 0,
+            direction: [0.0; 3],
+            range: 0.0,
+            color: [0.0; 3],
+            cos_inner: 0.0,
+            cos_outer: 0.0,
+            _padding: [0.0; 3],
+        };
+        match self {
+            Light::Directional { dir, color } => {
+                raw.kind = 0;
+                raw.direction = dir.into();
+                raw.color = color.into();
+            }
+            Light::Point { pos, color, range } => {
+                raw.kind = 1;
+                raw.position = pos.into();
+                raw.color = color.into();
+                raw.range = range;
+            }
+            Light::Spot {
+                pos,
+                dir,
+                color,
+                range,
+                inner,
+                outer,
+            } => {
+                raw.kind = 2;
+                raw.position = pos.into();
+                raw.direction = dir.into();
+                raw.color = color.into();
+                raw.range = range;
+                raw.cos_inner = inner.cos();
+                raw.cos_outer = outer.cos();
+            }
+        }
+        raw
+    }
+}
+
+/// Which light casts shadows, and over how much of the world.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ShadowSettings {
+    /// Only directional lights can cast shadows; None turns shadows off
+    pub caster: Option<LightId>,
+    /// The shadow map covers a box this far out in every direction from `center`
+    pub center: Pos3,
+    pub half_extent: f32,
+    /// Pushes shadow lookups back a little so surfaces don't shadow themselves
+    pub bias: f32,
+}
+
+impl ShadowSettings {
+    /// The light's view-projection matrix, if there is a directional caster.
+    fn view_proj(&self, lights: &BTreeMap<LightId, Light>) -> Option<Mat4> {
+        let dir = match lights.get(&self.caster?)? {
+            Light::Directional { dir, .. } => dir.normalize(),
+            _ => return None,
+        };
+        let eye = self.center - dir * (2.0 * self.half_extent);
+        // look_at breaks down if up is parallel to the view direction
+        let up = if dir.y.abs() > 0.99 {
+            Vec3::unit_z()
+        } else {
+            Vec3::unit_y()
+        };
+        let view = Mat4::look_at_rh(eye, self.center, up);
+        let e = self.half_extent;
+        let proj = cgmath::ortho(-e, e, -e, e, 0.0, 4.0 * e);
+        Some(OPENGL_TO_WGPU_MATRIX * proj * view)
+    }
+}
+
+pub struct Lights {
+    lights: BTreeMap<LightId, Light>,
+    next_id: usize,
+    pub shadows: ShadowSettings,
+    /// Light that reaches every surface no matter what
+    pub ambient: Vec3,
+    /// How bright highlights are, from 0 (matte) up
+    pub specular_strength: f32,
+    /// Blinn-Phong exponent; bigger means tighter highlights
+    pub shininess: f32,
+}
+
+impl Default for Lights {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lights {
+    pub fn new() -> Self {
+        Self {
+            lights: BTreeMap::new(),
+            next_id: 0,
+            shadows: ShadowSettings {
+                caster: None,
+                center: Pos3::new(0.0, 0.0, 0.0),
+                half_extent: 20.0,
+                bias: 0.002,
+            },
+            ambient: Vec3::new(0.1, 0.1, 0.1),
+            specular_strength: 0.5,
+            shininess: 32.0,
+        }
+    }
+
+    pub fn add(&mut self, light: Light) -> LightId {
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+        self.lights.insert(id, light);
+        id
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.lights.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.lights.get_mut(&id)
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Option<Light> {
+        self.lights.remove(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LightId, &Light)> {
+        self.lights.iter().map(|(id, l)| (*id, l))
+    }
+
+    pub(crate) fn shadow_view_proj(&self) -> Option<Mat4> {
+        self.shadows.view_proj(&self.lights)
+    }
+
+    pub(crate) fn to_uniform(&self) -> LightUniform {
+        let shadow_view_proj = self.shadow_view_proj();
+        let mut uniform = LightUniform {
+            ambient: self.ambient.into(),
+            num_lights: 0,
+            specular_strength: self.specular_strength,
+            shininess: self.shininess,
+            shadow_light: NO_SHADOW_LIGHT,
+            shadow_bias: self.shadows.bias,
+            shadow_view_proj: shadow_view_proj.unwrap_or_else(Mat4::identity).into(),
+            lights: [Light::directional(Vec3::unit_y(), Vec3::new(0.0, 0.0, 0.0)).to_raw();
+                MAX_LIGHTS],
+        };
+        // Anything past MAX_LIGHTS just doesn't get drawn
+        for ((raw, (id, light)), i) in uniform.lights.iter_mut().zip(self.lights.iter()).zip(0..) {
+            *raw = light.to_raw();
+            uniform.num_lights += 1;
+            if shadow_view_proj.is_some() && self.shadows.caster == Some(*id) {
+                uniform.shadow_light = i;
+            }
+        }
+        uniform
+    }
+}
+
+// Laid out to match std140, hence the padding
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightRaw {
+    position: [f32; 3],
+    kind: u32,
+    direction: [f32; 3],
+    range: f32,
+    color: [f32; 3],
+    cos_inner: f32,
+    cos_outer: f32,
+    _padding: [f32; 3],
+}
+
+// Has to match NO_SHADOW_LIGHT in shader.frag
+const NO_SHADOW_LIGHT: u32 = 0xffffffff;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightUniform {
+    ambient: [f32; 3],
+    num_lights: u32,
+    specular_strength: f32,
+    shininess: f32,
+    // index into lights of the shadow caster, or NO_SHADOW_LIGHT
+    shadow_light: u32,
+    shadow_bias: f32,
+    shadow_view_proj: [[f32; 4]; 4],
+    lights: [LightRaw; MAX_LIGHTS],
+}
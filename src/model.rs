@@ -3,6 +3,7 @@ use std::path::Path;
 use std::{ops::Range, rc::Rc};
 use wgpu::util::DeviceExt;
 
+use crate::geom::{Box, Pos3};
 use crate::texture;
 
 pub trait Vertex {
@@ -15,6 +16,8 @@ pub struct ModelVertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
     normal: [f32; 3],
+    tangent: [f32; 3],
+    bitangent: [f32; 3],
 }
 
 #[repr(C)]
@@ -68,6 +71,16 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float3,
+                },
             ],
         }
     }
@@ -78,19 +91,18 @@ impl Vertex for ModelVertex {
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
+    pub normal_texture: texture::Texture,
     pub bind_group: wgpu::BindGroup,
 }
 
 impl Material {
-    pub fn load<P: AsRef<Path>>(
-        name: String,
+    pub fn new(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
+        name: String,
+        diffuse_texture: texture::Texture,
+        normal_texture: texture::Texture,
         layout: &wgpu::BindGroupLayout,
-        path: P,
-    ) -> Result<Rc<Self>> {
-        let diffuse_texture = texture::Texture::load(device, queue, path)?;
-
+    ) -> Self {
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
@@ -102,15 +114,43 @@ impl Material {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+                },
             ],
-            label: None,
+            label: Some(&name),
         });
 
-        Ok(Rc::new(Material {
-            name: name,
-            diffuse_texture: diffuse_texture,
-            bind_group: bind_group,
-        }))
+        Material {
+            name,
+            diffuse_texture,
+            normal_texture,
+            bind_group,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(
+        name: String,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        path: P,
+    ) -> Result<Rc<Self>> {
+        let diffuse_texture = texture::Texture::load(device, queue, path, false)?;
+        let normal_texture = texture::Texture::flat_normal_map(device, queue)?;
+
+        Ok(Rc::new(Material::new(
+            device,
+            name,
+            diffuse_texture,
+            normal_texture,
+            layout,
+        )))
     }
 }
 
@@ -125,6 +165,40 @@ pub struct Mesh {
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    /// Axis-aligned box around every mesh, in model space
+    pub bounds: Box,
+    /// Every mesh's triangles, kept on the CPU for building colliders
+    pub triangles: Triangles,
+}
+
+/// Bare geometry: positions, and every three indices make a triangle.
+/// Meshes are all merged together.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Triangles {
+    pub positions: Vec<Pos3>,
+    pub indices: Vec<u32>,
+}
+
+impl Triangles {
+    /// Just the geometry from an OBJ file, without touching the GPU
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let (obj_models, _obj_materials) = tobj::load_obj(path.as_ref(), true)?;
+        let mut triangles = Triangles::default();
+        for m in obj_models.iter() {
+            triangles.add_mesh(&m.mesh);
+        }
+        Ok(triangles)
+    }
+
+    fn add_mesh(&mut self, mesh: &tobj::Mesh) {
+        let base = self.positions.len() as u32;
+        self.positions.extend(
+            mesh.positions
+                .chunks(3)
+                .map(|p| Pos3::new(p[0], p[1], p[2])),
+        );
+        self.indices.extend(mesh.indices.iter().map(|i| base + i));
+    }
 }
 
 impl Model {
@@ -142,33 +216,49 @@ impl Model {
         let mut materials = Vec::new();
         for mat in obj_materials {
             let diffuse_path = mat.diffuse_texture;
-            let diffuse_texture =
-                texture::Texture::load(device, queue, containing_folder.join(diffuse_path))?;
+            let diffuse_texture = texture::Texture::load(
+                device,
+                queue,
+                containing_folder.join(diffuse_path),
+                false,
+            )?;
 
-            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    },
-                ],
-                label: None,
-            });
+            // tobj puts map_Bump and bump in normal_texture, but not norm
+            let normal_path = if !mat.normal_texture.is_empty() {
+                Some(&mat.normal_texture)
+            } else {
+                mat.unknown_param.get("norm")
+            };
+            let normal_texture = match normal_path {
+                Some(normal_path) => texture::Texture::load(
+                    device,
+                    queue,
+                    containing_folder.join(normal_path),
+                    true,
+                )?,
+                None => texture::Texture::flat_normal_map(device, queue)?,
+            };
 
-            materials.push(Material {
-                name: mat.name,
+            materials.push(Material::new(
+                device,
+                mat.name,
                 diffuse_texture,
-                bind_group,
-            });
+                normal_texture,
+                layout,
+            ));
         }
 
         let mut meshes = Vec::new();
+        let mut triangles = Triangles::default();
+        let mut lo = [f32::INFINITY; 3];
+        let mut hi = [f32::NEG_INFINITY; 3];
         for m in obj_models {
+            for p in m.mesh.positions.chunks(3) {
+                for i in 0..3 {
+                    lo[i] = lo[i].min(p[i]);
+                    hi[i] = hi[i].max(p[i]);
+                }
+            }
             let mut vertices = Vec::new();
             for i in 0..m.mesh.positions.len() / 3 {
                 vertices.push(ModelVertex {
@@ -183,8 +273,13 @@ impl Model {
                         m.mesh.normals[i * 3 + 1],
                         m.mesh.normals[i * 3 + 2],
                     ],
+                    // Filled in below
+                    tangent: [0.0; 3],
+                    bitangent: [0.0; 3],
                 });
             }
+            compute_tangents(&mut vertices, &m.mesh.indices);
+            triangles.add_mesh(&m.mesh);
 
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", path.as_ref())),
@@ -206,7 +301,85 @@ impl Model {
             });
         }
 
-        Ok(Self { meshes, materials })
+        let bounds = Box {
+            c: Pos3::new(
+                (lo[0] + hi[0]) / 2.0,
+                (lo[1] + hi[1]) / 2.0,
+                (lo[2] + hi[2]) / 2.0,
+            ),
+            r: Pos3::new(
+                (hi[0] - lo[0]) / 2.0,
+                (hi[1] - lo[1]) / 2.0,
+                (hi[2] - lo[2]) / 2.0,
+            ),
+        };
+
+        Ok(Self {
+            meshes,
+            materials,
+            bounds,
+            triangles,
+        })
+    }
+}
+
+/// Work out each vertex's tangent and bitangent from how the texture
+/// coordinates run across its triangles, averaging over shared vertices.
+fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    use cgmath::{InnerSpace, Vector2, Vector3};
+    for c in indices.chunks(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: Vector3<f32> = v0.position.into();
+        let pos1: Vector3<f32> = v1.position.into();
+        let pos2: Vector3<f32> = v2.position.into();
+
+        let uv0: Vector2<f32> = v0.tex_coords.into();
+        let uv1: Vector2<f32> = v1.tex_coords.into();
+        let uv2: Vector2<f32> = v2.tex_coords.into();
+
+        // The triangle's edges, in model space and in texture space
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        let det = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        // Degenerate texture coordinates don't tell us anything
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * r;
+
+        for &i in c {
+            let v = &mut vertices[i as usize];
+            v.tangent = (Vector3::from(v.tangent) + tangent).into();
+            v.bitangent = (Vector3::from(v.bitangent) + bitangent).into();
+        }
+    }
+    for v in vertices.iter_mut() {
+        let normal = Vector3::from(v.normal);
+        let tangent = Vector3::from(v.tangent);
+        let bitangent = Vector3::from(v.bitangent);
+        if tangent.magnitude2() > 0.0 && bitangent.magnitude2() > 0.0 {
+            v.tangent = tangent.normalize().into();
+            v.bitangent = bitangent.normalize().into();
+        } else {
+            // No usable texture coordinates, so any basis around the normal
+            // will do; the shader would get NaNs from a zero vector.
+            let other = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            let tangent = normal.cross(other).normalize();
+            v.tangent = tangent.into();
+            v.bitangent = normal.cross(tangent).into();
+        }
     }
 }
 
@@ -270,3 +443,49 @@ where
         }
     }
 }
+
+pub trait DrawShadow<'a, 'b>
+where
+    'b: 'a,
+{
+    fn draw_mesh_shadow_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        instances: Range<u32>,
+        light: &'b wgpu::BindGroup,
+    );
+    fn draw_model_shadow_instanced(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        light: &'b wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawShadow<'a, 'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_mesh_shadow_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        instances: Range<u32>,
+        light: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, light, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_model_shadow_instanced(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        light: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            self.draw_mesh_shadow_instanced(mesh, instances.clone(), light);
+        }
+    }
+}
@@ -1,24 +1,60 @@
-use std::rc::Rc;
-
-use crate::{events::Events, geom::{Rect, Vec3}, model::Material, shapes::Ball};
-use cgmath::{prelude::*};
-use winit::event::VirtualKeyCode;
-pub const DT: f32 = 1.0 / 60.0;
+use crate::{assets::MaterialRef, ecs::{join_mut, Storage}, events::Events, geom::{solid_sphere_inertia, Mat3, Pos3, Quat, Rect, Vec3}, shapes::Ball};
+use cgmath::prelude::*;
 
 pub struct Physics {
+    // Worked out from momentum every step by the integrator
     pub velocity: Vec3,
     pub momentum: Vec3,
+    // Accumulated over a step, then cleared by the integrator
     pub force: Vec3,
+    // Worked out from angular momentum every step by the integrator
+    pub angular_velocity: Vec3,
+    pub angular_momentum: Vec3,
+    // Accumulated over a step, then cleared by the integrator
+    pub torque: Vec3,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Physics {
+    pub fn new() -> Self {
+        Self {
+            velocity: Vec3::zero(),
+            momentum: Vec3::zero(),
+            force: Vec3::zero(),
+            angular_velocity: Vec3::zero(),
+            angular_momentum: Vec3::zero(),
+            torque: Vec3::zero(),
+        }
+    }
     pub fn apply_impulse(&mut self, f: Vec3) {
         self.momentum += f;
     }
+    /// Push on the body for the rest of this step
+    pub fn add_force(&mut self, f: Vec3) {
+        self.force += f;
+    }
+    /// Hit the body with impulse `j` at `r`, an offset from its center.
+    /// Off-center hits set it spinning too.
+    pub fn apply_impulse_at(&mut self, j: Vec3, r: Vec3) {
+        self.momentum += j;
+        self.angular_momentum += r.cross(j);
+    }
+    /// Twist the body for the rest of this step
+    pub fn add_torque(&mut self, t: Vec3) {
+        self.torque += t;
+    }
     pub fn reset(&mut self) {
         self.velocity = Vec3::zero();
         self.momentum = Vec3::zero();
         self.force = Vec3::zero();
+        self.angular_velocity = Vec3::zero();
+        self.angular_momentum = Vec3::zero();
+        self.torque = Vec3::zero();
     }
 }
 
@@ -41,51 +77,268 @@ impl Force {
     }
 }
 
+/// Anything the integrator can move around.
+pub trait RigidBody {
+    fn position(&self) -> Pos3;
+    fn set_position(&mut self, p: Pos3);
+    fn mass(&self) -> f32;
+    /// Bodies that don't turn can leave the rest of these alone.
+    fn orientation(&self) -> Quat {
+        Quat::one()
+    }
+    fn set_orientation(&mut self, _q: Quat) {}
+    /// Inverse of the inertia tensor in world space.  Zero means nothing
+    /// can make the body spin.
+    fn inverse_inertia(&self) -> Mat3 {
+        Mat3::zero()
+    }
+    /// Bodies that are sitting out, like a ball waiting to be shot, are
+    /// left alone.
+    fn is_simulated(&self) -> bool {
+        true
+    }
+}
+
+impl RigidBody for Ball {
+    fn position(&self) -> Pos3 {
+        self.body.c
+    }
+    fn set_position(&mut self, p: Pos3) {
+        self.body.c = p;
+    }
+    fn mass(&self) -> f32 {
+        self.mass
+    }
+    fn orientation(&self) -> Quat {
+        self.orientation
+    }
+    fn set_orientation(&mut self, q: Quat) {
+        self.orientation = q;
+    }
+    fn inverse_inertia(&self) -> Mat3 {
+        // Spheres have the same inertia every way round, so we can skip
+        // rotating it into world space
+        solid_sphere_inertia(self.mass, self.body.r).invert().unwrap()
+    }
+    fn is_simulated(&self) -> bool {
+        self.play
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Integration {
+    /// Update momentum first, then move with the new velocity.  Cheap and
+    /// stable, which is why it's the default.
+    SemiImplicitEuler,
+    /// Move with the old velocity plus half the acceleration, treating the
+    /// step's forces as constant.  Exact for constant forces like gravity.
+    VelocityVerlet,
+}
+
+/// Moves bodies according to their momentum and the forces on them.  Knows
+/// nothing about input, so any game can use it for any `RigidBody`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Integrator {
+    pub gravity: Vec3,
+    /// Fraction of velocity lost per second, roughly; 0 for none
+    pub linear_damping: f32,
+    /// Same, but for spin
+    pub angular_damping: f32,
+    pub method: Integration,
+}
+
+impl Default for Integrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Integrator {
+    pub fn new() -> Self {
+        Integrator {
+            gravity: Vec3::new(0.0, -3.0, 0.0),
+            linear_damping: 0.0,
+            angular_damping: 0.0,
+            method: Integration::SemiImplicitEuler,
+        }
+    }
+
+    /// Advance every body that has `Physics` by `dt`
+    pub fn update<B: RigidBody>(&self, bodies: &mut Storage<B>, physics: &mut Storage<Physics>, dt: f32) {
+        for (_, b, p) in join_mut(bodies, physics) {
+            if !b.is_simulated() {
+                continue;
+            }
+            let mass = b.mass();
+            p.add_force(self.gravity * mass);
+            let accel = p.force / mass;
+            match self.method {
+                Integration::SemiImplicitEuler => {
+                    p.momentum += p.force * dt;
+                    b.set_position(b.position() + p.momentum / mass * dt);
+                }
+                Integration::VelocityVerlet => {
+                    let velocity = p.momentum / mass;
+                    b.set_position(b.position() + velocity * dt + accel * (0.5 * dt * dt));
+                    p.momentum += p.force * dt;
+                }
+            }
+            // Same as multiplying by 1 - damping * dt for small steps, but
+            // can't flip the velocity around for big ones
+            p.momentum /= 1.0 + self.linear_damping * dt;
+            p.velocity = p.momentum / mass;
+            p.force = Vec3::zero();
+
+            p.angular_momentum += p.torque * dt;
+            p.angular_momentum /= 1.0 + self.angular_damping * dt;
+            p.angular_velocity = b.inverse_inertia() * p.angular_momentum;
+            p.torque = Vec3::zero();
+            // dq/dt = ½ ω q, renormalized so rounding doesn't shrink it
+            let q = b.orientation();
+            let spin = Quat::from_sv(0.0, p.angular_velocity) * q;
+            b.set_orientation((q + spin * (0.5 * dt)).normalize());
+        }
+    }
+}
+
+/// Turns the player's input into shots: the "power" axis winds the shot
+/// up and down and the "shoot" action lets it go.  Gravity and movement
+/// are up to an `Integrator`.
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct BallMovement {
-    gravity: Force,
     pub player_mag: f32,
 }
 
 impl BallMovement {
     pub fn new() -> Self {
         BallMovement {
-            gravity: Force::new(Vec3::new(0.0, -3.0, 0.0)),
             player_mag: 0.0,
         }
     }
 
-    pub fn update(&mut self, events: &Events, balls: &mut Vec<Ball>, meter: &mut (Rect, f32, Rc<Material>), physics: &mut Vec<Physics>) {
-        if events.key_held(VirtualKeyCode::Up) {
+    pub fn update(&mut self, events: &Events, balls: &mut Storage<Ball>, meter: &mut (Rect, f32, MaterialRef), physics: &mut Storage<Physics>, dt: f32) {
+        let power = events.axis("power");
+        if power > 0.0 {
             if self.player_mag < 40.0 {
                 self.player_mag += 2.0;
                 meter.0.w += 0.03;
                 meter.1 += 0.05;
             }
-        } else if events.key_held(VirtualKeyCode::Down) {
-            if self.player_mag > 0.0 {
-                self.player_mag -= 2.0;
-                meter.0.w -= 0.03;
-                meter.1 -= 0.05;
-            }
+        } else if power < 0.0 && self.player_mag > 0.0 {
+            self.player_mag -= 2.0;
+            meter.0.w -= 0.03;
+            meter.1 -= 0.05;
         }
 
-        for (b, p) in balls.iter_mut().zip(physics.iter_mut()) {
-            if events.key_pressed(VirtualKeyCode::Space) {
+        for (_, b, p) in join_mut(balls, physics) {
+            if events.action_pressed("shoot") {
                 b.play = true;
                 let x = self.player_mag * b.yaw.sin() * (-b.pitch).cos();
                 let y = self.player_mag * b.yaw.sin() * (-b.pitch).sin();
                 let z = self.player_mag * b.yaw.cos();
                 let player_force = Force::new(Vec3::new(x, y, z));
-                p.momentum += player_force.apply_force() * DT;
-            }
-            if b.play {
-                p.momentum += (b.mass * self.gravity.apply_force()) * DT;
-                let vel = p.momentum / b.mass;
-                b.body.c += vel * DT;
+                p.momentum += player_force.apply_force() * dt;
             }
         }
 
         // println!("{:?}", self.player_force)f
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::{Entities, Entity};
+
+    const DT: f32 = 0.1;
+    const STEPS: usize = 10;
+
+    /// A point mass; enough for the integrator
+    struct Point(Pos3);
+
+    impl RigidBody for Point {
+        fn position(&self) -> Pos3 {
+            self.0
+        }
+        fn set_position(&mut self, p: Pos3) {
+            self.0 = p;
+        }
+        fn mass(&self) -> f32 {
+            2.0
+        }
+    }
+
+    /// Drop a point from rest at the origin and return its height and
+    /// vertical velocity after `STEPS` steps
+    fn drop(method: Integration) -> (f32, f32) {
+        let mut entities = Entities::new();
+        let mut points = Storage::new();
+        let mut physics = Storage::new();
+        let e: Entity = entities.spawn();
+        points.insert(e, Point(Pos3::new(0.0, 0.0, 0.0)));
+        physics.insert(e, Physics::new());
+        let integrator = Integrator {
+            method,
+            ..Integrator::new()
+        };
+        for _ in 0..STEPS {
+            integrator.update(&mut points, &mut physics, DT);
+        }
+        (points.get(e).unwrap().0.y, physics.get(e).unwrap().velocity.y)
+    }
+
+    #[test]
+    fn euler_falls_by_the_sum_of_its_steps() {
+        let g = Integrator::new().gravity.y;
+        let n = STEPS as f32;
+        let (y, vy) = drop(Integration::SemiImplicitEuler);
+        // Each step moves with the velocity at its end: g dt² (1 + 2 + ... + n)
+        assert!((y - g * DT * DT * n * (n + 1.0) / 2.0).abs() < 1e-4, "{}", y);
+        assert!((vy - g * DT * n).abs() < 1e-4, "{}", vy);
+    }
+
+    #[test]
+    fn verlet_is_exact_under_gravity() {
+        let g = Integrator::new().gravity.y;
+        let t = DT * STEPS as f32;
+        let (y, vy) = drop(Integration::VelocityVerlet);
+        assert!((y - 0.5 * g * t * t).abs() < 1e-4, "{}", y);
+        assert!((vy - g * t).abs() < 1e-4, "{}", vy);
+    }
+
+    #[test]
+    fn euler_overshoots_verlet_by_half_a_step() {
+        let g = Integrator::new().gravity.y;
+        let t = DT * STEPS as f32;
+        let (euler, _) = drop(Integration::SemiImplicitEuler);
+        let (verlet, _) = drop(Integration::VelocityVerlet);
+        assert!((euler - verlet - 0.5 * g * DT * t).abs() < 1e-4, "{} vs {}", euler, verlet);
+    }
+
+    #[test]
+    fn bodies_sitting_out_stay_put() {
+        let mut entities = Entities::new();
+        let mut balls = Storage::new();
+        let mut physics = Storage::new();
+        let e = entities.spawn();
+        balls.insert(
+            e,
+            Ball {
+                body: crate::geom::Sphere {
+                    c: Pos3::new(0.0, 1.0, 0.0),
+                    r: 0.5,
+                },
+                orientation: Quat::one(),
+                pitch: 0.0,
+                yaw: 0.0,
+                mass: 1.0,
+                play: false,
+                filter: Default::default(),
+            },
+        );
+        physics.insert(e, Physics::new());
+        Integrator::new().update(&mut balls, &mut physics, DT);
+        assert_eq!(balls.get(e).unwrap().body.c, Pos3::new(0.0, 1.0, 0.0));
+        assert_eq!(physics.get(e).unwrap().momentum, Vec3::zero());
+    }
+}
@@ -1,46 +1,86 @@
 use crate::camera::Camera;
+use crate::config::EngineConfig;
+use crate::interpolate::{Interpolated, Transform};
+use crate::physics::RigidBody;
+use crate::light::Lights;
 use crate::model::*;
 use crate::texture;
 use crate::Game;
 use crate::{
-    assets::{Assets, ModelRef},
-    geom::Rect,
+    assets::{Assets, MaterialRef, ModelRef},
+    ecs::{join, Storage},
+    geom::{Mat4, Pos3, Ray, RayHit, Rect, Shape},
 };
-use cgmath::SquareMatrix;
-use std::{collections::BTreeMap, rc::Rc};
+use cgmath::prelude::*;
+use std::collections::BTreeMap;
 use wgpu::util::DeviceExt;
 
 use winit::window::Window;
+
+// Width and height of the shadow map in texels
+const SHADOW_MAP_SIZE: u32 = 2048;
+
 pub(crate) struct Render {
-    surface: wgpu::Surface,
+    // Both None for offscreen renderers
+    surface: Option<wgpu::Surface>,
+    swap_chain: Option<wgpu::SwapChain>,
     pub(crate) device: wgpu::Device,
     pub(crate) queue: wgpu::Queue,
     sc_desc: wgpu::SwapChainDescriptor,
-    swap_chain: wgpu::SwapChain,
     pub(crate) size: winit::dpi::PhysicalSize<u32>,
     render_3d_pipeline: wgpu::RenderPipeline,
     render_2d_pipeline: wgpu::RenderPipeline,
     pub(crate) texture_layout: wgpu::BindGroupLayout,
-    pub(crate) camera: Camera,
     uniforms: Uniforms,
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
+    light_buffer: wgpu::Buffer,
+    shadow_pipeline: wgpu::RenderPipeline,
+    shadow_map: texture::Texture,
+    shadow_uniform_buffer: wgpu::Buffer,
+    shadow_bind_group: wgpu::BindGroup,
+    // Whether last frame's lights had a shadow caster
+    shadows_enabled: bool,
     depth_texture: texture::Texture,
+    clear_color: wgpu::Color,
+    // Samples per pixel, and the multisampled color target that gets
+    // resolved into the real one when that's more than 1
+    sample_count: u32,
+    msaa_target: Option<wgpu::TextureView>,
+    // Color target for offscreen captures, created on first use
+    offscreen_texture: Option<wgpu::Texture>,
     instance_groups: InstanceGroups,
 }
 
 impl Render {
-    pub(crate) async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
-
+    pub(crate) async fn new(window: &Window, config: &EngineConfig) -> Self {
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
         let surface = unsafe { instance.create_surface(window) };
+        Self::with_surface(instance, Some(surface), window.inner_size(), config).await
+    }
+
+    /// A renderer with no window that can only draw into offscreen targets.
+    /// Any adapter will do, including software ones like lavapipe or SwiftShader.
+    pub(crate) async fn new_offscreen(
+        size: winit::dpi::PhysicalSize<u32>,
+        config: &EngineConfig,
+    ) -> Self {
+        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        Self::with_surface(instance, None, size, config).await
+    }
+
+    async fn with_surface(
+        instance: wgpu::Instance,
+        surface: Option<wgpu::Surface>,
+        size: winit::dpi::PhysicalSize<u32>,
+        config: &EngineConfig,
+    ) -> Self {
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
+                compatible_surface: surface.as_ref(),
             })
             .await
             .unwrap();
@@ -58,13 +98,18 @@ impl Render {
 
         let sc_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
-            format: adapter.get_swap_chain_preferred_format(&surface),
+            format: match &surface {
+                Some(surface) => adapter.get_swap_chain_preferred_format(surface),
+                None => wgpu::TextureFormat::Rgba8UnormSrgb,
+            },
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: config.present_mode.to_wgpu(),
         };
 
-        let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+        let swap_chain = surface
+            .as_ref()
+            .map(|surface| device.create_swap_chain(surface, &sc_desc));
 
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -88,22 +133,31 @@ impl Render {
                         },
                         count: None,
                     },
+                    // Normal map
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler {
+                            comparison: false,
+                            filtering: true,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
 
-        let camera = Camera {
-            eye: (-15.0, 5.0, -15.0).into(),
-            target: (0.0, 0.0, 0.0).into(),
-            up: cgmath::Vector3::unit_y(),
-            aspect: sc_desc.width as f32 / sc_desc.height as f32,
-            fovy: 45.0,
-            znear: 0.1,
-            zfar: 200.0,
-        };
-
-        let mut uniforms = Uniforms::new();
-        uniforms.update_view_proj(&camera);
+        let uniforms = Uniforms::new();
 
         let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Uniform Buffer"),
@@ -111,7 +165,94 @@ impl Render {
             usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
         });
 
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[Lights::new().to_uniform()]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
         let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        // The fragment shader needs the eye position for specular highlights
+                        visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Shadow map
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler {
+                            comparison: true,
+                            filtering: true,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("uniform_bind_group_layout"),
+            });
+
+        let shadow_map =
+            texture::Texture::create_shadow_map(&device, SHADOW_MAP_SIZE, "shadow_map");
+
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&shadow_map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&shadow_map.sampler),
+                },
+            ],
+            label: Some("uniform_bind_group"),
+        });
+
+        // The shadow pass only needs the light's view-projection matrix
+        let shadow_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[Uniforms::new().view_proj]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let shadow_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
@@ -123,20 +264,22 @@ impl Render {
                     },
                     count: None,
                 }],
-                label: Some("uniform_bind_group_layout"),
+                label: Some("shadow_bind_group_layout"),
             });
 
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniform_bind_group_layout,
+        let shadow_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &shadow_bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: uniform_buffer.as_entire_binding(),
+                resource: shadow_uniform_buffer.as_entire_binding(),
             }],
-            label: Some("uniform_bind_group"),
+            label: Some("shadow_bind_group"),
         });
 
+        let sample_count = config.msaa_samples;
         let depth_texture =
-            texture::Texture::create_depth_texture(&device, &sc_desc, "depth_texture");
+            texture::Texture::create_depth_texture(&device, &sc_desc, sample_count, "depth_texture");
+        let msaa_target = Self::create_msaa_target(&device, &sc_desc, sample_count);
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -152,6 +295,52 @@ impl Render {
                 push_constant_ranges: &[],
             });
 
+        let shadow_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Shadow Pipeline Layout"),
+                bind_group_layouts: &[&shadow_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let shadow_vs_module = device.create_shader_module(&wgpu::include_spirv!("shadow.vert.spv"));
+
+        // Depth only, so there's no fragment stage
+        let shadow_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&shadow_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shadow_vs_module,
+                entry_point: "main",
+                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::Back,
+                polygon_mode: wgpu::PolygonMode::Fill,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                // Slope-scaled bias keeps steep surfaces from shadowing themselves
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+                clamp_depth: false,
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        });
+
         let vs_module = device.create_shader_module(&wgpu::include_spirv!("shader.vert.spv"));
         let fs_module = device.create_shader_module(&wgpu::include_spirv!("shader.frag.spv"));
 
@@ -194,7 +383,7 @@ impl Render {
                 clamp_depth: false,
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -236,14 +425,15 @@ impl Render {
                 clamp_depth: false,
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
         });
 
+        let [r, g, b] = config.clear_color;
         Self {
-            instance_groups: InstanceGroups::new(&device),
+            instance_groups: InstanceGroups::new(),
             surface,
             device,
             queue,
@@ -252,28 +442,92 @@ impl Render {
             size,
             render_3d_pipeline,
             render_2d_pipeline,
-            camera,
             uniform_buffer,
             uniform_bind_group,
             uniforms,
+            light_buffer,
+            shadow_pipeline,
+            shadow_map,
+            shadow_uniform_buffer,
+            shadow_bind_group,
+            shadows_enabled: false,
             texture_layout: texture_bind_group_layout,
             depth_texture,
+            clear_color: wgpu::Color {
+                r: r as f64,
+                g: g as f64,
+                b: b as f64,
+                a: 1.0,
+            },
+            sample_count,
+            msaa_target,
+            offscreen_texture: None,
+        }
+    }
+
+    /// A color target to draw into with `sample_count` samples per pixel,
+    /// or None if that's just 1
+    fn create_msaa_target(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        sample_count: u32,
+    ) -> Option<wgpu::TextureView> {
+        if sample_count == 1 {
+            return None;
         }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("msaa_target"),
+            size: wgpu::Extent3d {
+                width: sc_desc.width,
+                height: sc_desc.height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: sc_desc.format,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+        });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
     }
 
-    pub(crate) fn update_buffers<R, G: Game<StaticData = R>>(
+    pub(crate) fn update_buffers<G: Game>(
         &mut self,
         game: &G,
         assets: &mut Assets,
+        camera: &Camera,
+        lights: &Lights,
+        alpha: f32,
     ) {
-        self.uniforms.update_view_proj(&self.camera);
+        self.uniforms.update_view_proj(camera);
         self.queue.write_buffer(
             &self.uniform_buffer,
             0,
             bytemuck::cast_slice(&[self.uniforms]),
         );
+        self.queue.write_buffer(
+            &self.light_buffer,
+            0,
+            bytemuck::cast_slice(&[lights.to_uniform()]),
+        );
+        let shadow_view_proj = lights.shadow_view_proj();
+        self.shadows_enabled = shadow_view_proj.is_some();
+        if let Some(view_proj) = shadow_view_proj {
+            let view_proj: [[f32; 4]; 4] = view_proj.into();
+            self.queue.write_buffer(
+                &self.shadow_uniform_buffer,
+                0,
+                bytemuck::cast_slice(&[view_proj]),
+            );
+        }
         self.instance_groups.clear();
-        game.render(&mut self.instance_groups);
+        let ctx = RenderContext {
+            camera,
+            assets,
+            viewport_size: (self.size.width as f32, self.size.height as f32),
+            alpha,
+        };
+        game.render(&ctx, &mut self.instance_groups);
         self.instance_groups
             .update_buffers(&self.queue, &self.device, assets);
     }
@@ -282,20 +536,35 @@ impl Render {
         self.size = new_size;
         self.sc_desc.width = new_size.width;
         self.sc_desc.height = new_size.height;
-        self.camera.aspect = self.sc_desc.width as f32 / self.sc_desc.height as f32;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
-        self.depth_texture =
-            texture::Texture::create_depth_texture(&self.device, &self.sc_desc, "depth_texture");
+        if let Some(surface) = &self.surface {
+            self.swap_chain = Some(self.device.create_swap_chain(surface, &self.sc_desc));
+        }
+        self.depth_texture = texture::Texture::create_depth_texture(
+            &self.device,
+            &self.sc_desc,
+            self.sample_count,
+            "depth_texture",
+        );
+        self.msaa_target = Self::create_msaa_target(&self.device, &self.sc_desc, self.sample_count);
+        self.offscreen_texture = None;
     }
 
-    pub(crate) fn render<R, G: Game<StaticData = R>>(
+    pub(crate) fn render<G: Game>(
         &mut self,
         game: &G,
         assets: &mut Assets,
+        camera: &Camera,
+        lights: &Lights,
+        alpha: f32,
     ) -> Result<(), wgpu::SwapChainError> {
-        self.update_buffers(game, assets);
+        self.update_buffers(game, assets, camera, lights, alpha);
 
-        let frame = self.swap_chain.get_current_frame()?.output;
+        let frame = self
+            .swap_chain
+            .as_ref()
+            .expect("offscreen renderers have no swap chain")
+            .get_current_frame()?
+            .output;
 
         let mut encoder = self
             .device
@@ -303,68 +572,259 @@ impl Render {
                 label: Some("Render Encoder"),
             });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
+        self.draw(&mut encoder, &frame.view, assets);
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(())
+    }
+
+    /// Draw the same frame `render` would, but into an offscreen texture,
+    /// and read it back from the GPU.
+    pub(crate) fn capture<G: Game>(
+        &mut self,
+        game: &G,
+        assets: &mut Assets,
+        camera: &Camera,
+        lights: &Lights,
+        alpha: f32,
+    ) -> image::RgbaImage {
+        self.update_buffers(game, assets, camera, lights, alpha);
+
+        let width = self.sc_desc.width;
+        let height = self.sc_desc.height;
+        if self.offscreen_texture.is_none() {
+            self.offscreen_texture = Some(self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("offscreen_texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                // Same format as the swap chain so the pipelines can draw into it
+                format: self.sc_desc.format,
+                usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+            }));
+        }
+        let texture = self.offscreen_texture.as_ref().unwrap();
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Rows copied out of a texture have to be padded to a multiple of 256 bytes
+        let unpadded_bytes_per_row = 4 * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen_buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
             });
 
-            render_pass.set_pipeline(&self.render_3d_pipeline);
-            for (mr, (irs, buf, _cap)) in self.instance_groups.groups.iter() {
-                render_pass.set_vertex_buffer(1, buf.as_ref().unwrap().slice(..));
-                render_pass.draw_model_instanced(
-                    assets.get_model(*mr).unwrap(),
-                    0..irs.len() as u32,
-                    &self.uniform_bind_group,
-                );
-            }
+        self.draw(&mut encoder, &view, assets);
 
-            render_pass.set_pipeline(&self.render_2d_pipeline);
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &output_buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: padded_bytes_per_row,
+                    rows_per_image: height,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+        );
 
-            for (_rect, _power, mat, buffer) in self.instance_groups.groups_2d.iter() {
-                render_pass.set_vertex_buffer(0, buffer.as_ref().unwrap().slice(..));
-                render_pass.set_bind_group(0, &mat.bind_group, &[]);
-                render_pass.draw(0..4, 0..1);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = output_buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapping).unwrap();
+
+        let bgra = matches!(
+            self.sc_desc.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                for px in row[..unpadded_bytes_per_row as usize].chunks(4) {
+                    if bgra {
+                        pixels.extend_from_slice(&[px[2], px[1], px[0], px[3]]);
+                    } else {
+                        pixels.extend_from_slice(px);
+                    }
+                }
             }
         }
+        output_buffer.unmap();
 
-        self.queue.submit(std::iter::once(encoder.finish()));
+        image::RgbaImage::from_raw(width, height, pixels).unwrap()
+    }
 
-        Ok(())
+    fn draw(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, assets: &Assets) {
+        if self.shadows_enabled {
+            self.draw_shadows(encoder, assets);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                // With multisampling, draw into the multisampled target
+                // and resolve that into the real one
+                attachment: self.msaa_target.as_ref().unwrap_or(target),
+                resolve_target: self.msaa_target.as_ref().map(|_| target),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.clear_color),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(&self.render_3d_pipeline);
+        for (mr, (irs, buf, _cap)) in self.instance_groups.groups.iter() {
+            render_pass.set_vertex_buffer(1, buf.as_ref().unwrap().slice(..));
+            render_pass.draw_model_instanced(
+                assets.get_model(*mr).unwrap(),
+                0..irs.len() as u32,
+                &self.uniform_bind_group,
+            );
+        }
+
+        render_pass.set_pipeline(&self.render_2d_pipeline);
+
+        for (_rect, _power, mr, buffer) in self.instance_groups.groups_2d.iter() {
+            render_pass.set_vertex_buffer(0, buffer.as_ref().unwrap().slice(..));
+            render_pass.set_bind_group(0, &assets.get_material(*mr).unwrap().bind_group, &[]);
+            render_pass.draw(0..4, 0..1);
+        }
     }
+
+    /// Render every 3D instance's depth from the shadow caster's point of view.
+    fn draw_shadows(&self, encoder: &mut wgpu::CommandEncoder, assets: &Assets) {
+        let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.shadow_map.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        shadow_pass.set_pipeline(&self.shadow_pipeline);
+        for (mr, (irs, buf, _cap)) in self.instance_groups.groups.iter() {
+            shadow_pass.set_vertex_buffer(1, buf.as_ref().unwrap().slice(..));
+            shadow_pass.draw_model_shadow_instanced(
+                assets.get_model(*mr).unwrap(),
+                0..irs.len() as u32,
+                &self.shadow_bind_group,
+            );
+        }
+    }
+}
+
+/// What a game can look at while it's deciding what to draw
+#[derive(Clone, Copy)]
+pub struct RenderContext<'a> {
+    pub camera: &'a Camera,
+    pub assets: &'a Assets,
+    /// Size of what's being drawn into, in pixels
+    pub viewport_size: (f32, f32),
+    /// How far (0 to 1) the frame is from the last simulation step to the
+    /// next one, for drawing moving things in between
+    pub alpha: f32,
 }
 
 pub struct InstanceGroups {
     groups: BTreeMap<ModelRef, (Vec<InstanceRaw>, Option<wgpu::Buffer>, usize)>,
-    groups_2d: Vec<(Rect, Rect, Rc<Material>, Option<wgpu::Buffer>)>,
+    groups_2d: Vec<(Rect, Rect, MaterialRef, Option<wgpu::Buffer>)>,
 }
+/// Which instance a pick hit
+#[derive(Clone, Copy, Debug)]
+pub struct InstancePick {
+    pub model: ModelRef,
+    /// Index among the instances of `model`, in the order they were rendered
+    pub index: usize,
+    pub hit: RayHit,
+}
+
 impl InstanceGroups {
-    fn new(_device: &wgpu::Device) -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             groups: BTreeMap::new(),
             groups_2d: vec![],
         }
     }
+    /// The closest 3D instance along `ray`, going by its model's bounding
+    /// box.  Models that were never loaded, like when running headless,
+    /// can't be hit.
+    pub(crate) fn pick(&self, assets: &Assets, ray: &Ray) -> Option<InstancePick> {
+        let mut best: Option<InstancePick> = None;
+        for (mr, (irs, _buf, _cap)) in self.groups.iter() {
+            let bounds = match assets.get_model(*mr) {
+                Some(model) => model.bounds,
+                None => continue,
+            };
+            for (index, ir) in irs.iter().enumerate() {
+                let model = Mat4::from(ir.model);
+                let inv = match model.invert() {
+                    Some(inv) => inv,
+                    None => continue,
+                };
+                // Test in model space.  dir isn't unit length there, but
+                // distances along it still match world space.
+                let local = Ray {
+                    origin: Pos3::from_homogeneous(inv * ray.origin.to_homogeneous()),
+                    dir: (inv * ray.dir.extend(0.0)).truncate(),
+                };
+                let max_dist = best.map_or(f32::INFINITY, |b| b.hit.dist);
+                if let Some(hit) = bounds.cast_ray(&local, max_dist) {
+                    let normal = inv.transpose() * hit.normal.extend(0.0);
+                    best = Some(InstancePick {
+                        model: *mr,
+                        index,
+                        hit: RayHit {
+                            dist: hit.dist,
+                            point: ray.at(hit.dist),
+                            normal: normal.truncate().normalize(),
+                        },
+                    });
+                }
+            }
+        }
+        best
+    }
     fn clear(&mut self) {
         for (_mr, (irs, _buf, _cap)) in self.groups.iter_mut() {
             irs.clear();
@@ -432,8 +892,38 @@ impl InstanceGroups {
             .0
             .extend(ir.into_iter())
     }
+    /// Draw every entity that has both a model and a `T`
+    pub fn render_each<T>(
+        &mut self,
+        models: &Storage<ModelRef>,
+        things: &Storage<T>,
+        to_raw: impl Fn(&T) -> InstanceRaw,
+    ) {
+        for (_, mr, thing) in join(models, things) {
+            self.render(*mr, to_raw(thing));
+        }
+    }
+
+    /// Like `render_each`, but bodies that are `Interpolated` are drawn
+    /// `alpha` of the way between their last two steps.  The rest are
+    /// drawn where they are.
+    pub fn render_each_interpolated<B: RigidBody>(
+        &mut self,
+        models: &Storage<ModelRef>,
+        bodies: &Storage<B>,
+        interpolated: &Storage<Interpolated>,
+        alpha: f32,
+        to_raw: impl Fn(&B, &Transform) -> InstanceRaw,
+    ) {
+        for (e, mr, body) in join(models, bodies) {
+            let t = interpolated
+                .get(e)
+                .map_or_else(|| Transform::of(body), |i| i.at(alpha));
+            self.render(*mr, to_raw(body, &t));
+        }
+    }
 
-    pub fn render_bar(&mut self, rect: &Rect, power: f32, mat: &Rc<Material>) {
+    pub fn render_bar(&mut self, rect: &Rect, power: f32, mat: MaterialRef) {
         let tex_rect = Rect {
             x: 0.0,
             y: 0.0,
@@ -441,11 +931,11 @@ impl InstanceGroups {
             h: 1.0
         };
 
-        self.groups_2d.push((*rect, tex_rect, Rc::clone(mat), None));
+        self.groups_2d.push((*rect, tex_rect, mat, None));
     }
 
-    pub fn render_2d(&mut self, rect: &Rect, tex_rect: &Rect, mat: &Rc<Material>) {
-        self.groups_2d.push((*rect, *tex_rect, Rc::clone(mat), None));
+    pub fn render_2d(&mut self, rect: &Rect, tex_rect: &Rect, mat: MaterialRef) {
+        self.groups_2d.push((*rect, *tex_rect, mat, None));
     }
 }
 
@@ -507,16 +997,19 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct Uniforms {
     view_proj: [[f32; 4]; 4],
+    view_position: [f32; 4],
 }
 
 impl Uniforms {
     fn new() -> Self {
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            view_position: [0.0; 4],
         }
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
         self.view_proj = (OPENGL_TO_WGPU_MATRIX * camera.build_view_projection_matrix()).into();
+        self.view_position = camera.eye.to_homogeneous().into();
     }
 }
@@ -0,0 +1,129 @@
+use crate::assets::{load_data, save_data};
+use crate::events::InputFrame;
+use crate::input::Bindings;
+use crate::rng::Rngs;
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The input a game got at every step of a session, and the bindings and
+/// random number state it started with.  Since the simulation takes
+/// fixed steps, feeding the same input back in plays the session out the
+/// same way again.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub bindings: Bindings,
+    pub rng: Rngs,
+    pub frames: Vec<InputFrame>,
+}
+
+impl Recording {
+    pub fn new(bindings: Bindings, rng: Rngs) -> Self {
+        Recording {
+            bindings,
+            rng,
+            frames: vec![],
+        }
+    }
+
+    /// Read a recording, as RON if the file ends in `.ron` and JSON
+    /// otherwise
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        load_data(path, "recording")
+    }
+
+    /// Write the recording out, in the same format `load` would pick
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        save_data(self, path, "recording")
+    }
+
+    /// How many steps it covers
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::KeyCode;
+    use crate::input::Input;
+    use crate::render::{InstanceGroups, RenderContext};
+    use crate::{run_replay, Engine, Game};
+    use rand::Rng;
+    use winit::event::{DeviceId, ElementState, KeyboardInput, WindowEvent};
+
+    /// Rolls a die every time "shoot" is pressed, and keeps track of the
+    /// rolls and of when "shoot" was held
+    #[derive(PartialEq, Debug)]
+    struct Dice {
+        rolls: Vec<u32>,
+        held: Vec<bool>,
+    }
+
+    impl Game for Dice {
+        fn start(engine: &mut Engine) -> Self {
+            engine.events.bindings.bind("shoot", Input::Key(KeyCode::Space));
+            Dice {
+                rolls: vec![],
+                held: vec![],
+            }
+        }
+        fn update(&mut self, engine: &mut Engine) {
+            if engine.events.action_pressed("shoot") {
+                self.rolls.push(engine.rng.stream("dice").gen_range(1..=6));
+            }
+            self.held.push(engine.events.action_held("shoot"));
+        }
+        fn render(&self, _ctx: &RenderContext, _igs: &mut InstanceGroups) {}
+    }
+
+    fn space(state: ElementState) -> WindowEvent<'static> {
+        // There's no other way to fill in `modifiers`
+        #[allow(deprecated)]
+        WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(KeyCode::Space),
+                modifiers: Default::default(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    #[test]
+    fn replay_plays_out_the_same() {
+        let path = std::env::temp_dir().join("game3d_engine_replay_test.json");
+        let mut engine = Engine::headless(Path::new("content"));
+        engine.rng.reseed(21);
+        let mut game = Dice::start(&mut engine);
+        engine.start_recording(&path);
+        for step in 0..12 {
+            match step {
+                1 | 6 | 9 => engine.events.window_event(&space(ElementState::Pressed)),
+                3 | 7 | 10 => engine.events.window_event(&space(ElementState::Released)),
+                _ => {}
+            }
+            engine.step(&mut game);
+        }
+        engine.stop_recording().unwrap();
+        assert_eq!(game.rolls.len(), 3);
+
+        let recording = Recording::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recording.len(), 12);
+        let (mut replayed_engine, replayed) = run_replay::<Dice>(Path::new("content"), recording);
+        assert_eq!(replayed, game);
+        // The random streams were left in the same state too
+        assert_eq!(
+            replayed_engine.rng.stream("dice").gen::<u64>(),
+            engine.rng.stream("dice").gen::<u64>()
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The engine's randomness.  Every system draws from its own named
+/// stream, so one of them drawing more numbers doesn't change what any
+/// other gets, and the same seed always gives the same numbers.  The
+/// whole thing serializes, so it can be saved along with the game.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Rngs {
+    seed: u64,
+    streams: BTreeMap<String, Pcg32>,
+}
+
+impl Rngs {
+    pub fn new(seed: u64) -> Self {
+        Rngs {
+            seed,
+            streams: BTreeMap::new(),
+        }
+    }
+
+    /// Seeded from the clock, for when runs shouldn't all be alike
+    pub fn from_time() -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Self::new(now.as_nanos() as u64)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Start every stream over from `seed`
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.streams.clear();
+    }
+
+    /// The stream called `name`, which starts the first time it's asked
+    /// for
+    pub fn stream(&mut self, name: &str) -> &mut Pcg32 {
+        let seed = self.seed;
+        self.streams
+            .entry(name.to_string())
+            .or_insert_with(|| Pcg32::seed_from_u64(seed ^ hash_name(name)))
+    }
+}
+
+// FNV-1a, since the streams have to come out the same on every platform
+// and every compiler version
+fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -0,0 +1,470 @@
+use crate::assets::{load_data, save_data, ModelRef};
+use crate::collision::CollisionFilter;
+use crate::ecs::{Entity, World};
+use crate::geom::*;
+use crate::interpolate::{Interpolated, Transform};
+use crate::light::{Light, LightId};
+use crate::physics::Physics;
+use crate::shapes::{Ball, Goal, Static, StaticShape};
+use crate::Engine;
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A level as it's written on disk: which models it uses, where everything
+/// starts, the lights and the camera.  Plain data, so it can be edited by
+/// hand as JSON or RON and then turned into a `Level` with `instantiate`.
+///
+/// Positions and sizes are `[x, y, z]`, rotations are Euler angles in
+/// degrees, and colors are `[r, g, b]`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Scene {
+    /// Model files, relative to the asset root, by the name everything
+    /// else in the scene uses for them
+    pub models: BTreeMap<String, String>,
+    #[serde(default)]
+    pub statics: Vec<StaticDesc>,
+    pub goal: GoalDesc,
+    #[serde(default)]
+    pub balls: Vec<BallDesc>,
+    #[serde(default)]
+    pub lights: Vec<LightDesc>,
+    #[serde(default)]
+    pub ambient: Option<[f32; 3]>,
+    #[serde(default)]
+    pub shadows: Option<ShadowDesc>,
+    #[serde(default)]
+    pub camera: Option<CameraDesc>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StaticDesc {
+    /// So game code can find this static's entity in `Level::named_statics`
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Which of the scene's models to draw it with.  Mesh shapes collide
+    /// with this model's triangles too.
+    pub model: String,
+    pub shape: ShapeDesc,
+    #[serde(default)]
+    pub filter: CollisionFilter,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum ShapeDesc {
+    /// `r` is the half size along x and z before rotating
+    Quad { c: [f32; 3], rot: [f32; 3], r: [f32; 2] },
+    Aabb { c: [f32; 3], r: [f32; 3] },
+    Obb { c: [f32; 3], rot: [f32; 3], r: [f32; 3] },
+    Mesh { c: [f32; 3], rot: [f32; 3], scale: f32 },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GoalDesc {
+    pub c: [f32; 3],
+    pub r: [f32; 3],
+    #[serde(default = "goal_filter")]
+    pub filter: CollisionFilter,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BallDesc {
+    pub model: String,
+    pub c: [f32; 3],
+    pub r: f32,
+    pub mass: f32,
+    #[serde(default)]
+    pub filter: CollisionFilter,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum LightDesc {
+    Directional {
+        dir: [f32; 3],
+        color: [f32; 3],
+    },
+    Point {
+        pos: [f32; 3],
+        color: [f32; 3],
+        range: f32,
+    },
+    /// `inner` and `outer` are in degrees here, unlike `Light::Spot`
+    Spot {
+        pos: [f32; 3],
+        dir: [f32; 3],
+        color: [f32; 3],
+        range: f32,
+        inner: f32,
+        outer: f32,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ShadowDesc {
+    /// Index into the scene's lights; has to be a directional one
+    pub light: usize,
+    pub center: [f32; 3],
+    pub half_extent: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CameraDesc {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+}
+
+/// What a `Scene` turned into once its models were loaded.  Statics are
+/// entities with a `Static` and the `ModelRef` they're drawn with; balls
+/// have a `Ball`, `Physics` and `ModelRef`.
+pub struct Level {
+    pub models: BTreeMap<String, ModelRef>,
+    /// In the order the scene lists them
+    pub statics: Vec<Entity>,
+    pub named_statics: BTreeMap<String, Entity>,
+    pub goal: Goal,
+    /// In the order the scene lists them
+    pub balls: Vec<Entity>,
+    /// The lights this scene added, in the order it lists them
+    pub lights: Vec<LightId>,
+}
+
+impl Scene {
+    /// Read a scene, as RON if the file ends in `.ron` and JSON otherwise
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        load_data(path, "scene")
+    }
+
+    /// Write the scene out, in the same format `load` would pick
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        save_data(self, path, "scene")
+    }
+
+    /// Load every model the scene uses, add its lights, point the camera
+    /// and spawn the bodies into `world`
+    pub fn instantiate(&self, engine: &mut Engine, world: &mut World) -> Result<Level> {
+        let models: BTreeMap<String, ModelRef> = self
+            .models
+            .iter()
+            .map(|(name, path)| (name.clone(), engine.load_model(path)))
+            .collect();
+        let model = |name: &str| {
+            models
+                .get(name)
+                .copied()
+                .ok_or_else(|| anyhow!("Scene uses model {:?} but doesn't list it", name))
+        };
+
+        let mut statics = vec![];
+        let mut named_statics = BTreeMap::new();
+        for desc in self.statics.iter() {
+            let mref = model(&desc.model)?;
+            let stat = match desc.shape {
+                ShapeDesc::Quad { c, rot, r } => Static::quad(c.into(), from_euler(rot), r),
+                ShapeDesc::Aabb { c, r } => Static::aabb(c.into(), r.into()),
+                ShapeDesc::Obb { c, rot, r } => Static::obb(c.into(), from_euler(rot), r.into()),
+                ShapeDesc::Mesh { c, rot, scale } => {
+                    Static::mesh(engine.assets.trimesh(mref), c.into(), from_euler(rot), scale)
+                }
+            };
+            let e = world.spawn();
+            world.insert(e, stat.with_filter(desc.filter));
+            world.insert(e, mref);
+            if let Some(name) = &desc.name {
+                named_statics.insert(name.clone(), e);
+            }
+            statics.push(e);
+        }
+
+        let goal = Goal {
+            body: Box {
+                c: self.goal.c.into(),
+                r: self.goal.r.into(),
+            },
+            filter: self.goal.filter,
+        };
+
+        let mut balls = vec![];
+        for desc in self.balls.iter() {
+            let mref = model(&desc.model)?;
+            let e = world.spawn();
+            world.insert(e, Ball {
+                body: Sphere {
+                    c: desc.c.into(),
+                    r: desc.r,
+                },
+                orientation: Quat::one(),
+                pitch: 0.0,
+                yaw: 0.0,
+                mass: desc.mass,
+                play: false,
+                filter: desc.filter,
+            });
+            world.insert(e, Physics::new());
+            world.insert(e, Interpolated::new(Transform {
+                pos: desc.c.into(),
+                rot: Quat::one(),
+            }));
+            world.insert(e, mref);
+            balls.push(e);
+        }
+
+        let lights: Vec<LightId> = self
+            .lights
+            .iter()
+            .map(|desc| engine.add_light(desc.to_light()))
+            .collect();
+        if let Some(ambient) = self.ambient {
+            engine.lights_mut().ambient = ambient.into();
+        }
+        if let Some(shadows) = self.shadows {
+            let caster = *lights
+                .get(shadows.light)
+                .ok_or_else(|| anyhow!("Shadows come from light {} but there isn't one", shadows.light))?;
+            let settings = &mut engine.lights_mut().shadows;
+            settings.caster = Some(caster);
+            settings.center = shadows.center.into();
+            settings.half_extent = shadows.half_extent;
+        }
+        if let Some(camera) = self.camera {
+            let cam = engine.camera_mut();
+            cam.eye = camera.eye.into();
+            cam.target = camera.target.into();
+        }
+
+        Ok(Level {
+            models,
+            statics,
+            named_statics,
+            goal,
+            balls,
+            lights,
+        })
+    }
+
+    /// A scene that would recreate `level` as it is right now in `world`,
+    /// along with the engine's lights and camera.  Anything despawned since
+    /// is left out.
+    pub fn capture(engine: &Engine, world: &World, level: &Level) -> Result<Self> {
+        let names: BTreeMap<ModelRef, &String> =
+            level.models.iter().map(|(name, mref)| (*mref, name)).collect();
+        let name_of = |mref: &ModelRef| {
+            names
+                .get(mref)
+                .map(|name| (*name).clone())
+                .ok_or_else(|| anyhow!("Level uses {:?}, which isn't one of its models", mref))
+        };
+        let models = level
+            .models
+            .iter()
+            .map(|(name, mref)| {
+                let path = engine.assets.path_for_model_ref(*mref);
+                (name.clone(), path.to_string_lossy().into_owned())
+            })
+            .collect();
+
+        let all_models = world.storage::<ModelRef>();
+        let all_statics = world.storage::<Static>();
+        let all_balls = world.storage::<Ball>();
+
+        let static_names: BTreeMap<Entity, &String> =
+            level.named_statics.iter().map(|(name, e)| (*e, name)).collect();
+        let mut statics = vec![];
+        for e in level.statics.iter() {
+            let (stat, mref) = match (all_statics.get(*e), all_models.get(*e)) {
+                (Some(stat), Some(mref)) => (stat, mref),
+                _ => continue,
+            };
+            let shape = match &stat.body {
+                StaticShape::Quad(q) => ShapeDesc::Quad {
+                    c: q.c.into(),
+                    rot: to_euler(q.rot),
+                    r: q.r,
+                },
+                StaticShape::Aabb(b) => ShapeDesc::Aabb {
+                    c: b.c.into(),
+                    r: b.r.into(),
+                },
+                StaticShape::Obb(b) => ShapeDesc::Obb {
+                    c: b.c.into(),
+                    rot: to_euler(b.rot),
+                    r: b.r.into(),
+                },
+                StaticShape::Mesh(m) => ShapeDesc::Mesh {
+                    c: m.c.into(),
+                    rot: to_euler(m.rot),
+                    scale: m.scale,
+                },
+            };
+            statics.push(StaticDesc {
+                name: static_names.get(e).map(|name| (*name).clone()),
+                model: name_of(mref)?,
+                shape,
+                filter: stat.filter,
+            });
+        }
+
+        let mut balls = vec![];
+        for e in level.balls.iter() {
+            let (ball, mref) = match (all_balls.get(*e), all_models.get(*e)) {
+                (Some(ball), Some(mref)) => (ball, mref),
+                _ => continue,
+            };
+            balls.push(BallDesc {
+                model: name_of(mref)?,
+                c: ball.body.c.into(),
+                r: ball.body.r,
+                mass: ball.mass,
+                filter: ball.filter,
+            });
+        }
+
+        let all_lights = engine.lights();
+        let lights = level
+            .lights
+            .iter()
+            .filter_map(|id| all_lights.get(*id))
+            .map(LightDesc::from_light)
+            .collect();
+        let shadows = all_lights.shadows.caster.and_then(|caster| {
+            Some(ShadowDesc {
+                light: level.lights.iter().position(|id| *id == caster)?,
+                center: all_lights.shadows.center.into(),
+                half_extent: all_lights.shadows.half_extent,
+            })
+        });
+
+        let camera = engine.camera();
+        Ok(Scene {
+            models,
+            statics,
+            goal: GoalDesc {
+                c: level.goal.body.c.into(),
+                r: level.goal.body.r.into(),
+                filter: level.goal.filter,
+            },
+            balls,
+            lights,
+            ambient: Some(all_lights.ambient.into()),
+            shadows,
+            camera: Some(CameraDesc {
+                eye: camera.eye.into(),
+                target: camera.target.into(),
+            }),
+        })
+    }
+}
+
+impl LightDesc {
+    fn to_light(self) -> Light {
+        match self {
+            LightDesc::Directional { dir, color } => Light::directional(dir.into(), color.into()),
+            LightDesc::Point { pos, color, range } => {
+                Light::point(pos.into(), color.into(), range)
+            }
+            LightDesc::Spot {
+                pos,
+                dir,
+                color,
+                range,
+                inner,
+                outer,
+            } => Light::spot(
+                pos.into(),
+                dir.into(),
+                color.into(),
+                range,
+                inner.to_radians(),
+                outer.to_radians(),
+            ),
+        }
+    }
+
+    fn from_light(light: &Light) -> Self {
+        match *light {
+            Light::Directional { dir, color } => LightDesc::Directional {
+                dir: dir.into(),
+                color: color.into(),
+            },
+            Light::Point { pos, color, range } => LightDesc::Point {
+                pos: pos.into(),
+                color: color.into(),
+                range,
+            },
+            Light::Spot {
+                pos,
+                dir,
+                color,
+                range,
+                inner,
+                outer,
+            } => LightDesc::Spot {
+                pos: pos.into(),
+                dir: dir.into(),
+                color: color.into(),
+                range,
+                inner: inner.to_degrees(),
+                outer: outer.to_degrees(),
+            },
+        }
+    }
+}
+
+fn goal_filter() -> CollisionFilter {
+    CollisionFilter::trigger(1, CollisionFilter::ALL)
+}
+
+fn from_euler(deg: [f32; 3]) -> Quat {
+    Quat::from(cgmath::Euler::new(
+        cgmath::Deg(deg[0]),
+        cgmath::Deg(deg[1]),
+        cgmath::Deg(deg[2]),
+    ))
+}
+
+fn to_euler(rot: Quat) -> [f32; 3] {
+    let e = cgmath::Euler::from(rot);
+    [
+        cgmath::Deg::from(e.x).0,
+        cgmath::Deg::from(e.y).0,
+        cgmath::Deg::from(e.z).0,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_ron_and_json() {
+        let scene = Scene::load("content/level.ron").unwrap();
+        for ext in ["ron", "json"] {
+            let path = std::env::temp_dir().join(format!("game3d_engine_scene_test.{}", ext));
+            scene.save(&path).unwrap();
+            let loaded = Scene::load(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap(), scene, "through {}", ext);
+        }
+    }
+
+    #[test]
+    fn euler_angles_are_stable() {
+        let close = |a: [f32; 3], b: [f32; 3]| (0..3).all(|i| (a[i] - b[i]).abs() < 1e-3);
+        for deg in [
+            [0.0, 0.0, 0.0],
+            [90.0, 0.0, 0.0],
+            [0.0, 0.0, -45.0],
+            [30.0, -45.0, 60.0],
+            [-120.0, 80.0, 170.0],
+        ] {
+            let once = to_euler(from_euler(deg));
+            assert!(close(once, deg), "{:?} came back as {:?}", deg, once);
+            let rot = from_euler(deg);
+            let again = from_euler(once);
+            assert!(rot.dot(again).abs() > 1.0 - 1e-6);
+            assert!(close(to_euler(again), once));
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use crate::Engine;
+use anyhow::*;
+use std::time::{Duration, Instant};
+
+/// When in a frame a system runs.  Every system in a stage runs before
+/// any in the next one.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Stage {
+    Input,
+    PrePhysics,
+    Physics,
+    PostPhysics,
+    Audio,
+    RenderPrep,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 6] = [
+        Stage::Input,
+        Stage::PrePhysics,
+        Stage::Physics,
+        Stage::PostPhysics,
+        Stage::Audio,
+        Stage::RenderPrep,
+    ];
+}
+
+type SystemFn<C> = Box<dyn FnMut(&mut Engine, &mut C)>;
+
+/// A system to add to a `Schedule`: a name, a stage, what to run, and
+/// which other systems it has to come before or after.
+pub struct SystemDesc<C> {
+    name: String,
+    stage: Stage,
+    run: SystemFn<C>,
+    after: Vec<String>,
+    before: Vec<String>,
+}
+
+impl<C> SystemDesc<C> {
+    pub fn new(name: &str, stage: Stage, run: impl FnMut(&mut Engine, &mut C) + 'static) -> Self {
+        SystemDesc {
+            name: name.to_string(),
+            stage,
+            run: Box::new(run),
+            after: vec![],
+            before: vec![],
+        }
+    }
+
+    /// Run after the system called `other`
+    pub fn after(mut self, other: &str) -> Self {
+        self.after.push(other.to_string());
+        self
+    }
+
+    /// Run before the system called `other`
+    pub fn before(mut self, other: &str) -> Self {
+        self.before.push(other.to_string());
+        self
+    }
+}
+
+/// How long a system took
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SystemTiming<'a> {
+    pub name: &'a str,
+    pub stage: Stage,
+    /// The last time it ran
+    pub last: Duration,
+    /// Smoothed over the last few dozen runs
+    pub average: Duration,
+}
+
+// How much each run moves the average
+const AVERAGE_WEIGHT: f64 = 0.05;
+
+/// Runs systems over a game's data `C` once a frame, stage by stage, in
+/// an order that respects every `after` and `before`.  Within a stage,
+/// systems with no constraints between them run in the order they were
+/// added.
+pub struct Schedule<C> {
+    systems: Vec<SystemDesc<C>>,
+    // last and average run time of each system, the average in seconds
+    // and None until it's run
+    timings: Vec<(Duration, Option<f64>)>,
+    // indices into systems, in the order they run; None when systems
+    // have been added since it was worked out
+    order: Option<Vec<usize>>,
+}
+
+impl<C> Default for Schedule<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> Schedule<C> {
+    pub fn new() -> Self {
+        Schedule {
+            systems: vec![],
+            timings: vec![],
+            order: None,
+        }
+    }
+
+    pub fn add(&mut self, system: SystemDesc<C>) {
+        self.systems.push(system);
+        self.timings.push((Duration::default(), None));
+        self.order = None;
+    }
+
+    /// Names of the systems in the order they'll run.  Fails if a
+    /// constraint names a system that isn't there, asks a system to run
+    /// before one in an earlier stage, or the constraints go round in a
+    /// circle.
+    pub fn order(&mut self) -> Result<Vec<&str>> {
+        if self.order.is_none() {
+            self.order = Some(self.sort()?);
+        }
+        let systems = &self.systems;
+        let order = self.order.as_ref().unwrap();
+        Ok(order.iter().map(|&i| systems[i].name.as_str()).collect())
+    }
+
+    /// Run every system once.  Panics if they can't be put in order.
+    pub fn run(&mut self, engine: &mut Engine, c: &mut C) {
+        if self.order.is_none() {
+            self.order = Some(self.sort().unwrap());
+        }
+        let order = self.order.as_ref().unwrap();
+        for &i in order.iter() {
+            let start = Instant::now();
+            (self.systems[i].run)(engine, c);
+            let elapsed = start.elapsed();
+            let (last, average) = &mut self.timings[i];
+            *last = elapsed;
+            let secs = elapsed.as_secs_f64();
+            *average = Some(average.map_or(secs, |avg| avg + (secs - avg) * AVERAGE_WEIGHT));
+        }
+    }
+
+    /// How long each system took, in the order they run
+    pub fn timings(&self) -> impl Iterator<Item = SystemTiming<'_>> {
+        let order = self.order.as_deref().unwrap_or(&[]);
+        order.iter().map(move |&i| SystemTiming {
+            name: &self.systems[i].name,
+            stage: self.systems[i].stage,
+            last: self.timings[i].0,
+            average: Duration::from_secs_f64(self.timings[i].1.unwrap_or(0.0)),
+        })
+    }
+
+    fn find(&self, name: &str) -> Result<usize> {
+        self.systems
+            .iter()
+            .position(|s| s.name == name)
+            .ok_or_else(|| anyhow!("No system called {:?}", name))
+    }
+
+    fn sort(&self) -> Result<Vec<usize>> {
+        let n = self.systems.len();
+        for (i, s) in self.systems.iter().enumerate() {
+            if self.systems[..i].iter().any(|other| other.name == s.name) {
+                bail!("Two systems called {:?}", s.name);
+            }
+        }
+        // must_follow[i] lists what has to run before i in the same stage
+        let mut must_follow = vec![vec![]; n];
+        for (i, s) in self.systems.iter().enumerate() {
+            let edges = s
+                .after
+                .iter()
+                .map(|other| (other, true))
+                .chain(s.before.iter().map(|other| (other, false)));
+            for (other, after) in edges {
+                let j = self.find(other)?;
+                let (first, second) = if after { (j, i) } else { (i, j) };
+                let (a, b) = (&self.systems[first], &self.systems[second]);
+                if a.stage > b.stage {
+                    bail!(
+                        "{:?} has to run before {:?}, but it's in a later stage",
+                        a.name,
+                        b.name
+                    );
+                }
+                if a.stage == b.stage {
+                    must_follow[second].push(first);
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(n);
+        let mut done = vec![false; n];
+        for stage in Stage::ALL.iter() {
+            loop {
+                // The first system added that's ready to go
+                let next = (0..n).find(|&i| {
+                    !done[i]
+                        && self.systems[i].stage == *stage
+                        && must_follow[i].iter().all(|&j| done[j])
+                });
+                match next {
+                    Some(i) => {
+                        done[i] = true;
+                        order.push(i);
+                    }
+                    None => break,
+                }
+            }
+            if (0..n).any(|i| !done[i] && self.systems[i].stage == *stage) {
+                bail!("Systems in {:?} have constraints that go round in a circle", stage);
+            }
+        }
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system(name: &str, stage: Stage) -> SystemDesc<()> {
+        SystemDesc::new(name, stage, |_, _| {})
+    }
+
+    fn schedule(systems: Vec<SystemDesc<()>>) -> Schedule<()> {
+        let mut schedule = Schedule::new();
+        for s in systems {
+            schedule.add(s);
+        }
+        schedule
+    }
+
+    fn error(systems: Vec<SystemDesc<()>>) -> String {
+        schedule(systems).order().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn stages_then_constraints_then_insertion_order() {
+        let mut s = schedule(vec![
+            system("draw", Stage::RenderPrep),
+            system("integrate", Stage::Physics),
+            system("collide", Stage::Physics).before("integrate").after("aim"),
+            system("aim", Stage::Physics),
+            system("read", Stage::Input),
+            system("score", Stage::PostPhysics).after("collide"),
+        ]);
+        assert_eq!(
+            s.order().unwrap(),
+            vec!["read", "aim", "collide", "integrate", "score", "draw"]
+        );
+        // Adding a system works the order out again
+        s.add(system("sound", Stage::Audio));
+        assert_eq!(s.order().unwrap()[5], "sound");
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let e = error(vec![system("a", Stage::Input), system("a", Stage::Physics)]);
+        assert!(e.contains("Two systems"), "{}", e);
+    }
+
+    #[test]
+    fn unknown_targets() {
+        let e = error(vec![system("a", Stage::Input).after("nope")]);
+        assert!(e.contains("No system called \"nope\""), "{}", e);
+        let e = error(vec![system("a", Stage::Input).before("nope")]);
+        assert!(e.contains("No system called \"nope\""), "{}", e);
+    }
+
+    #[test]
+    fn across_stages() {
+        // Later stages run later anyway, so these are fine...
+        let mut s = schedule(vec![
+            system("a", Stage::Input).before("b"),
+            system("b", Stage::Physics).after("a"),
+        ]);
+        assert_eq!(s.order().unwrap(), vec!["a", "b"]);
+        // ...but these can't happen
+        let e = error(vec![
+            system("a", Stage::Input),
+            system("b", Stage::Physics).before("a"),
+        ]);
+        assert!(e.contains("later stage"), "{}", e);
+        let e = error(vec![
+            system("a", Stage::Input).after("b"),
+            system("b", Stage::Physics),
+        ]);
+        assert!(e.contains("later stage"), "{}", e);
+    }
+
+    #[test]
+    fn cycles() {
+        let e = error(vec![
+            system("a", Stage::Physics).after("c"),
+            system("b", Stage::Physics).after("a"),
+            system("c", Stage::Physics).after("b"),
+            system("d", Stage::Input),
+        ]);
+        assert!(e.contains("circle"), "{}", e);
+        let e = error(vec![system("a", Stage::Physics).before("a")]);
+        assert!(e.contains("circle"), "{}", e);
+    }
+}
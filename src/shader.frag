@@ -1,12 +1,129 @@
 #version 450
 
+// Has to match MAX_LIGHTS in light.rs
+#define MAX_LIGHTS 8
+
+#define LIGHT_DIRECTIONAL 0
+#define LIGHT_POINT 1
+#define LIGHT_SPOT 2
+
+// Has to match NO_SHADOW_LIGHT in light.rs
+#define NO_SHADOW_LIGHT 0xffffffffu
+
 layout(location=0) in vec2 v_tex_coords;
+layout(location=1) in vec3 v_position;
+layout(location=2) in vec3 v_normal;
+layout(location=3) in vec3 v_tangent;
+layout(location=4) in vec3 v_bitangent;
 
 layout(location=0) out vec4 f_color;
 
 layout(set = 0, binding = 0) uniform texture2D t_diffuse;
 layout(set = 0, binding = 1) uniform sampler s_diffuse;
+layout(set = 0, binding = 2) uniform texture2D t_normal;
+layout(set = 0, binding = 3) uniform sampler s_normal;
+
+layout(set=1, binding=0)
+uniform Uniforms {
+    mat4 u_view_proj;
+    vec4 u_view_position;
+};
+
+struct Light {
+    vec3 position;
+    uint kind;
+    vec3 direction;
+    float range;
+    vec3 color;
+    float cos_inner;
+    float cos_outer;
+};
+
+layout(set=1, binding=1)
+uniform Lights {
+    vec3 u_ambient;
+    uint u_num_lights;
+    float u_specular_strength;
+    float u_shininess;
+    uint u_shadow_light;
+    float u_shadow_bias;
+    mat4 u_shadow_view_proj;
+    Light u_lights[MAX_LIGHTS];
+};
+
+layout(set=1, binding=2) uniform texture2D t_shadow;
+layout(set=1, binding=3) uniform samplerShadow s_shadow;
+
+// How much of the shadow caster's light reaches this point, from 0 to 1.
+// Averages a 3x3 block of shadow map lookups (PCF) to soften the edges.
+float shadow_factor(vec3 world_position) {
+    vec4 light_space = u_shadow_view_proj * vec4(world_position, 1.0);
+    vec3 proj = light_space.xyz / light_space.w;
+    // Clip space y points up, texture v points down
+    vec2 uv = proj.xy * vec2(0.5, -0.5) + 0.5;
+    // Outside the shadow map counts as lit
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 || proj.z > 1.0) {
+        return 1.0;
+    }
+    float depth = proj.z - u_shadow_bias;
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DShadow(t_shadow, s_shadow), 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec2 offset = vec2(float(x), float(y)) * texel;
+            lit += texture(sampler2DShadow(t_shadow, s_shadow), vec3(uv + offset, depth));
+        }
+    }
+    return lit / 9.0;
+}
 
 void main() {
-    f_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
-}
\ No newline at end of file
+    vec4 object_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
+
+    // Normal maps are in tangent space: x along the tangent, y along the
+    // bitangent and z straight out of the surface.  Materials without one
+    // get a flat map, which leaves the mesh normal alone.
+    vec3 tangent_normal = texture(sampler2D(t_normal, s_normal), v_tex_coords).xyz * 2.0 - 1.0;
+    mat3 tbn = mat3(normalize(v_tangent), normalize(v_bitangent), normalize(v_normal));
+    vec3 normal = normalize(tbn * tangent_normal);
+    vec3 view_dir = normalize(u_view_position.xyz - v_position);
+
+    vec3 result = u_ambient * object_color.rgb;
+    for (uint i = 0; i < u_num_lights; i++) {
+        Light light = u_lights[i];
+
+        vec3 light_dir;
+        float attenuation = 1.0;
+        if (light.kind == LIGHT_DIRECTIONAL) {
+            light_dir = normalize(-light.direction);
+        } else {
+            vec3 to_light = light.position - v_position;
+            float dist = length(to_light);
+            light_dir = to_light / dist;
+            // Smooth falloff that reaches exactly zero at the light's range
+            float falloff = clamp(1.0 - pow(dist / light.range, 4.0), 0.0, 1.0);
+            attenuation = falloff * falloff / (dist * dist + 1.0);
+            if (light.kind == LIGHT_SPOT) {
+                float theta = dot(light_dir, normalize(-light.direction));
+                attenuation *= smoothstep(light.cos_outer, light.cos_inner, theta);
+            }
+        }
+
+        float diffuse = max(dot(normal, light_dir), 0.0);
+
+        // Blinn-Phong: use the half vector instead of the reflection vector
+        vec3 half_dir = normalize(view_dir + light_dir);
+        float specular = 0.0;
+        if (diffuse > 0.0) {
+            specular = pow(max(dot(normal, half_dir), 0.0), u_shininess) * u_specular_strength;
+        }
+
+        if (i == u_shadow_light) {
+            attenuation *= shadow_factor(v_position);
+        }
+
+        result += (diffuse * object_color.rgb + specular) * light.color * attenuation;
+    }
+
+    f_color = vec4(result, object_color.a);
+}
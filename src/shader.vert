@@ -2,12 +2,20 @@
 
 layout(location=0) in vec3 a_position;
 layout(location=1) in vec2 a_tex_coords;
+layout(location=2) in vec3 a_normal;
+layout(location=3) in vec3 a_tangent;
+layout(location=4) in vec3 a_bitangent;
 
 layout(location=0) out vec2 v_tex_coords;
+layout(location=1) out vec3 v_position;
+layout(location=2) out vec3 v_normal;
+layout(location=3) out vec3 v_tangent;
+layout(location=4) out vec3 v_bitangent;
 
 layout(set=1, binding=0)
 uniform Uniforms {
     mat4 u_view_proj;
+    vec4 u_view_position;
 };
 
 layout(location=5) in vec4 model_matrix_0;
@@ -22,6 +30,13 @@ void main() {
         model_matrix_2,
         model_matrix_3
     );
+    // Walls are scaled non-uniformly, so normals need the inverse transpose
+    mat3 normal_matrix = transpose(inverse(mat3(model_matrix)));
+    vec4 world_position = model_matrix * vec4(a_position, 1.0);
     v_tex_coords = a_tex_coords;
-    gl_Position = u_view_proj * model_matrix * vec4(a_position, 1.0);
-}
\ No newline at end of file
+    v_position = world_position.xyz;
+    v_normal = normal_matrix * a_normal;
+    v_tangent = normal_matrix * a_tangent;
+    v_bitangent = normal_matrix * a_bitangent;
+    gl_Position = u_view_proj * world_position;
+}
@@ -0,0 +1,23 @@
+#version 450
+
+layout(location=0) in vec3 a_position;
+
+layout(set=0, binding=0)
+uniform ShadowUniforms {
+    mat4 u_light_view_proj;
+};
+
+layout(location=5) in vec4 model_matrix_0;
+layout(location=6) in vec4 model_matrix_1;
+layout(location=7) in vec4 model_matrix_2;
+layout(location=8) in vec4 model_matrix_3;
+
+void main() {
+    mat4 model_matrix = mat4(
+        model_matrix_0,
+        model_matrix_1,
+        model_matrix_2,
+        model_matrix_3
+    );
+    gl_Position = u_light_view_proj * model_matrix * vec4(a_position, 1.0);
+}
@@ -1,25 +1,40 @@
 use cgmath::prelude::*;
 use cgmath::EuclideanSpace;
 use rand::Rng;
-use crate::{assets::ModelRef, render::InstanceGroups, render::InstanceRaw};
-use crate::geom::{Mat4, Plane, Sphere, Vec3, Box, Pos3};
+use crate::{assets::ModelRef, collision::CollisionFilter, render::InstanceGroups, render::InstanceRaw};
+use crate::trimesh::{MeshCollider, TriMesh};
+use crate::interpolate::Transform;
+use std::rc::Rc;
+use crate::geom::*;
 
 
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Ball {
     pub body: Sphere,
+    pub orientation: Quat,
     pub pitch: f32,
     pub yaw: f32,
     pub mass: f32,
     pub play: bool,
+    pub filter: CollisionFilter,
 }
 
 impl Ball {
     pub fn to_raw(&self) -> InstanceRaw {
+        self.to_raw_at(&Transform {
+            pos: self.body.c,
+            rot: self.orientation,
+        })
+    }
+
+    /// Draw the ball at `t` instead of where it is
+    pub fn to_raw_at(&self, t: &Transform) -> InstanceRaw {
         InstanceRaw {
-            model: (Mat4::from_translation(self.body.c.to_vec()) * Mat4::from_scale(self.body.r))
-                .into(),
+            model: (Mat4::from_translation(t.pos.to_vec())
+                * Mat4::from(t.rot)
+                * Mat4::from_scale(self.body.r))
+            .into(),
         }
     }
 
@@ -28,20 +43,91 @@ impl Ball {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+// wall.obj and floor.obj are 100 x 2 x 100, centered on the origin
+const WALL_MODEL_HALF: Vec3 = Vec3::new(50.0, 1.0, 50.0);
+// Quads have no thickness, but drawing them that way would look odd
+const QUAD_THICKNESS: f32 = 0.05;
+
+/// The shape of something that never moves.  All of them are finite, and
+/// drawn exactly as big as they collide.
+#[derive(Clone, PartialEq, Debug)]
+pub enum StaticShape {
+    Quad(Quad),
+    Aabb(Box),
+    Obb(Obb),
+    /// Drawn with whatever model the mesh came from, not the wall
+    Mesh(MeshCollider),
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct Static {
-    pub body: Plane,
-    pub position: Vec3, // control: (i8, i8),
+    pub body: StaticShape,
+    pub filter: CollisionFilter,
 }
 
 impl Static {
+    pub fn quad(c: Pos3, rot: Quat, r: [f32; 2]) -> Self {
+        Static {
+            body: StaticShape::Quad(Quad { c, rot, r }),
+            filter: CollisionFilter::default(),
+        }
+    }
+    pub fn aabb(c: Pos3, r: Pos3) -> Self {
+        Static {
+            body: StaticShape::Aabb(Box { c, r }),
+            filter: CollisionFilter::default(),
+        }
+    }
+    pub fn obb(c: Pos3, rot: Quat, r: Vec3) -> Self {
+        Static {
+            body: StaticShape::Obb(Obb { c, rot, r }),
+            filter: CollisionFilter::default(),
+        }
+    }
+
+    pub fn mesh(mesh: Rc<TriMesh>, c: Pos3, rot: Quat, scale: f32) -> Self {
+        Static {
+            body: StaticShape::Mesh(MeshCollider {
+                mesh,
+                c,
+                rot,
+                scale,
+            }),
+            filter: CollisionFilter::default(),
+        }
+    }
+
+    pub fn with_filter(self, filter: CollisionFilter) -> Self {
+        Static { filter, ..self }
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
+        let (c, rot, half) = match &self.body {
+            StaticShape::Quad(q) => {
+                // Sink the slab so its top face is where the quad is
+                let c = q.c - q.normal() * QUAD_THICKNESS;
+                (c, q.rot, Vec3::new(q.r[0], QUAD_THICKNESS, q.r[1]))
+            }
+            StaticShape::Aabb(b) => (b.c, Quat::one(), b.r.to_vec()),
+            StaticShape::Obb(b) => (b.c, b.rot, b.r),
+            StaticShape::Mesh(m) => {
+                // Meshes are drawn with their own model, at their own size
+                return InstanceRaw {
+                    model: (Mat4::from_translation(m.c.to_vec())
+                        * Mat4::from(m.rot)
+                        * Mat4::from_scale(m.scale))
+                    .into(),
+                };
+            }
+        };
         InstanceRaw {
-            model: (Mat4::from(cgmath::Quaternion::between_vectors(
-                Vec3::new(0.0, 1.0, 0.0),
-                self.body.n,
-            )) * Mat4::from_translation(self.position)
-                * Mat4::from_nonuniform_scale(0.5, 0.05, 0.5))
+            model: (Mat4::from_translation(c.to_vec())
+                * Mat4::from(rot)
+                * Mat4::from_nonuniform_scale(
+                    half.x / WALL_MODEL_HALF.x,
+                    half.y / WALL_MODEL_HALF.y,
+                    half.z / WALL_MODEL_HALF.z,
+                ))
             .into(),
         }
     }
@@ -49,10 +135,89 @@ impl Static {
     pub fn render(&self, wall_model: ModelRef, igs: &mut InstanceGroups) {
         igs.render(wall_model, self.to_raw());
     }
+
+    /// The smallest axis-aligned box containing the whole thing
+    pub fn bounds(&self) -> Box {
+        match &self.body {
+            StaticShape::Quad(q) => q.bounds(),
+            StaticShape::Aabb(b) => *b,
+            StaticShape::Obb(b) => b.bounds(),
+            StaticShape::Mesh(m) => m.bounds(),
+        }
+    }
+
+    /// What's the offset I'd need to push s out of this?
+    pub fn disp_sphere(&self, s: &Sphere) -> Option<Vec3> {
+        match &self.body {
+            StaticShape::Quad(q) => disp_sphere_quad(s, q),
+            StaticShape::Aabb(b) => disp_sphere_box(s, b),
+            StaticShape::Obb(b) => disp_sphere_obb(s, b),
+            StaticShape::Mesh(m) => m.disp_sphere(s),
+        }
+    }
+
+    /// If s moves by v, how far along (0 to 1) does it first touch this?
+    pub fn sweep_sphere(&self, s: &Sphere, v: Vec3) -> Option<f32> {
+        match &self.body {
+            StaticShape::Quad(q) => sweep_sphere_obb(s, v, &q.to_obb()),
+            StaticShape::Aabb(b) => sweep_sphere_box(s, v, b),
+            StaticShape::Obb(b) => sweep_sphere_obb(s, v, b),
+            StaticShape::Mesh(m) => m.sweep_sphere(s, v),
+        }
+    }
+
+    /// Which way is out, for something centered at p just touching this
+    pub fn normal_at(&self, p: Pos3) -> Vec3 {
+        if let StaticShape::Mesh(m) = &self.body {
+            return match m.closest_point(p) {
+                Some((q, n)) if q == p => n,
+                Some((q, _)) => (p - q).normalize(),
+                None => Vec3::unit_y(),
+            };
+        }
+        let q = match &self.body {
+            StaticShape::Quad(q) => closest_point_quad(p, q),
+            StaticShape::Aabb(b) => closest_point_box(p, b),
+            StaticShape::Obb(b) => closest_point_obb(p, b),
+            StaticShape::Mesh(_) => unreachable!(),
+        };
+        let offset = p - q;
+        if offset.magnitude2() > 0.0 {
+            offset.normalize()
+        } else if let StaticShape::Quad(q) = &self.body {
+            q.normal()
+        } else {
+            // Inside a box; the way out is the way disp_sphere pushes
+            self.disp_sphere(&Sphere { c: p, r: 0.0 })
+                .filter(|d| d.magnitude2() > 0.0)
+                .map_or(Vec3::unit_y(), |d| d.normalize())
+        }
+    }
+}
+
+impl Shape for Static {
+    fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        match &self.body {
+            StaticShape::Quad(q) => q.cast_ray(ray, max_dist),
+            StaticShape::Aabb(b) => b.cast_ray(ray, max_dist),
+            StaticShape::Obb(b) => b.cast_ray(ray, max_dist),
+            StaticShape::Mesh(m) => m.cast_ray(ray, max_dist),
+        }
+    }
+    fn cast_sphere(&self, s: &Sphere, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        match &self.body {
+            StaticShape::Quad(q) => q.cast_sphere(s, ray, max_dist),
+            StaticShape::Aabb(b) => b.cast_sphere(s, ray, max_dist),
+            StaticShape::Obb(b) => b.cast_sphere(s, ray, max_dist),
+            StaticShape::Mesh(m) => m.cast_sphere(s, ray, max_dist),
+        }
+    }
 }
 
 pub struct Goal {
     pub body: Box,
+    /// Usually a trigger, so balls fly into it instead of bouncing off
+    pub filter: CollisionFilter,
 }
 
 impl Goal {
@@ -68,8 +233,8 @@ impl Goal {
         igs.render(goal_model, self.to_raw());
     }
 
-    pub fn gen_new_loc(&mut self) {
-        let mut rng = rand::thread_rng();
+    /// Move somewhere random in the play area
+    pub fn gen_new_loc(&mut self, rng: &mut impl Rng) {
         let new_x = rng.gen_range(-12.5..-0.5);
         let new_y = rng.gen_range(0.5..7.0);
         let new_z = rng.gen_range(-12.5..-0.5);
@@ -0,0 +1,249 @@
+use crate::render::{InstanceGroups, RenderContext};
+use crate::Engine;
+
+/// What the state stack should do after a state's update
+pub enum Transition<C> {
+    Stay,
+    /// Put a new state on top, like a pause menu over gameplay
+    Push(Box<dyn State<C>>),
+    /// Leave this state and go back to the one under it
+    Pop,
+    /// Swap this state for another, like going from the title to gameplay
+    Replace(Box<dyn State<C>>),
+}
+
+/// One mode of a game: a title screen, gameplay, a pause menu.  `C` is
+/// whatever data the game's states share.
+pub trait State<C> {
+    /// Called when the state goes on the stack
+    fn enter(&mut self, _engine: &mut Engine, _c: &mut C) {}
+    /// Called when the state comes off the stack
+    fn exit(&mut self, _engine: &mut Engine, _c: &mut C) {}
+    fn update(&mut self, engine: &mut Engine, c: &mut C) -> Transition<C>;
+    fn render(&self, c: &C, ctx: &RenderContext, igs: &mut InstanceGroups);
+    /// Should the state under this one keep updating while this is on top?
+    fn update_below(&self) -> bool {
+        false
+    }
+    /// Should the state under this one still be drawn, under this one?
+    fn render_below(&self) -> bool {
+        false
+    }
+}
+
+/// A stack of states where the top one is in charge.  States further down
+/// update and draw only as long as everything above them lets them.
+pub struct StateStack<C> {
+    states: Vec<Box<dyn State<C>>>,
+}
+
+impl<C> Default for StateStack<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> StateStack<C> {
+    pub fn new() -> Self {
+        StateStack { states: vec![] }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn push(&mut self, mut state: Box<dyn State<C>>, engine: &mut Engine, c: &mut C) {
+        state.enter(engine, c);
+        self.states.push(state);
+    }
+
+    pub fn pop(&mut self, engine: &mut Engine, c: &mut C) -> Option<Box<dyn State<C>>> {
+        let mut state = self.states.pop()?;
+        state.exit(engine, c);
+        Some(state)
+    }
+
+    pub fn replace(&mut self, state: Box<dyn State<C>>, engine: &mut Engine, c: &mut C) {
+        self.pop(engine, c);
+        self.push(state, engine, c);
+    }
+
+    /// Update every state that's allowed to, bottom first.  Only the top
+    /// state's transition is followed; the ones under it are only along
+    /// for the ride.
+    pub fn update(&mut self, engine: &mut Engine, c: &mut C) {
+        let top = match self.states.len() {
+            0 => return,
+            n => n - 1,
+        };
+        let lowest = Self::lowest(&self.states, |s| s.update_below());
+        for state in self.states[lowest..top].iter_mut() {
+            state.update(engine, c);
+        }
+        match self.states[top].update(engine, c) {
+            Transition::Stay => {}
+            Transition::Push(state) => self.push(state, engine, c),
+            Transition::Pop => {
+                self.pop(engine, c);
+            }
+            Transition::Replace(state) => self.replace(state, engine, c),
+        }
+    }
+
+    /// Draw every state that's visible, bottom first so upper ones draw
+    /// over them
+    pub fn render(&self, c: &C, ctx: &RenderContext, igs: &mut InstanceGroups) {
+        if self.states.is_empty() {
+            return;
+        }
+        let lowest = Self::lowest(&self.states, |s| s.render_below());
+        for state in self.states[lowest..].iter() {
+            state.render(c, ctx, igs);
+        }
+    }
+
+    // Index of the lowest state reached by going down from the top while
+    // `see_through` holds
+    fn lowest(states: &[Box<dyn State<C>>], see_through: impl Fn(&dyn State<C>) -> bool) -> usize {
+        let mut i = states.len() - 1;
+        while i > 0 && see_through(states[i].as_ref()) {
+            i -= 1;
+        }
+        i
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::path::Path;
+
+    // What every state did, in order
+    type Log = RefCell<Vec<String>>;
+
+    struct Probe {
+        name: &'static str,
+        update_below: bool,
+        render_below: bool,
+        // What to do on the next update
+        next: Option<Transition<Log>>,
+    }
+
+    fn probe(name: &'static str) -> Box<Probe> {
+        Box::new(Probe {
+            name,
+            update_below: false,
+            render_below: false,
+            next: None,
+        })
+    }
+
+    impl State<Log> for Probe {
+        fn enter(&mut self, _engine: &mut Engine, c: &mut Log) {
+            c.borrow_mut().push(format!("enter {}", self.name));
+        }
+        fn exit(&mut self, _engine: &mut Engine, c: &mut Log) {
+            c.borrow_mut().push(format!("exit {}", self.name));
+        }
+        fn update(&mut self, _engine: &mut Engine, c: &mut Log) -> Transition<Log> {
+            c.borrow_mut().push(format!("update {}", self.name));
+            self.next.take().unwrap_or(Transition::Stay)
+        }
+        fn render(&self, c: &Log, _ctx: &RenderContext, _igs: &mut InstanceGroups) {
+            c.borrow_mut().push(format!("render {}", self.name));
+        }
+        fn update_below(&self) -> bool {
+            self.update_below
+        }
+        fn render_below(&self) -> bool {
+            self.render_below
+        }
+    }
+
+    fn take(log: &Log) -> Vec<String> {
+        log.borrow_mut().drain(..).collect()
+    }
+
+    fn render(stack: &StateStack<Log>, engine: &Engine, log: &Log) {
+        let ctx = RenderContext {
+            camera: engine.camera(),
+            assets: &engine.assets,
+            viewport_size: (1.0, 1.0),
+            alpha: 0.0,
+        };
+        stack.render(log, &ctx, &mut InstanceGroups::new());
+    }
+
+    #[test]
+    fn transitions_enter_and_exit() {
+        let mut engine = Engine::headless(Path::new("content"));
+        let mut log = Log::default();
+        let mut stack = StateStack::new();
+
+        let mut title = probe("title");
+        title.next = Some(Transition::Replace(probe("play")));
+        stack.push(title, &mut engine, &mut log);
+        assert_eq!(take(&log), ["enter title"]);
+
+        stack.update(&mut engine, &mut log);
+        assert_eq!(take(&log), ["update title", "exit title", "enter play"]);
+        assert_eq!(stack.len(), 1);
+
+        let mut pause = probe("pause");
+        pause.next = Some(Transition::Pop);
+        stack.push(pause, &mut engine, &mut log);
+        assert_eq!(stack.len(), 2);
+        stack.update(&mut engine, &mut log);
+        assert_eq!(take(&log), ["enter pause", "update pause", "exit pause"]);
+        assert_eq!(stack.len(), 1);
+
+        assert!(stack.pop(&mut engine, &mut log).is_some());
+        assert_eq!(take(&log), ["exit play"]);
+        assert!(stack.is_empty());
+        assert!(stack.pop(&mut engine, &mut log).is_none());
+        stack.update(&mut engine, &mut log);
+        assert!(take(&log).is_empty());
+    }
+
+    #[test]
+    fn below_flags_choose_what_runs() {
+        let mut engine = Engine::headless(Path::new("content"));
+        let mut log = Log::default();
+        let mut stack = StateStack::new();
+        stack.push(probe("play"), &mut engine, &mut log);
+        let mut hud = probe("hud");
+        hud.update_below = true;
+        hud.render_below = true;
+        stack.push(hud, &mut engine, &mut log);
+        let mut pause = probe("pause");
+        pause.render_below = true;
+        stack.push(pause, &mut engine, &mut log);
+        take(&log);
+
+        // The pause menu stops everything under it updating, but they
+        // all still show
+        stack.update(&mut engine, &mut log);
+        assert_eq!(take(&log), ["update pause"]);
+        render(&stack, &engine, &log);
+        assert_eq!(take(&log), ["render play", "render hud", "render pause"]);
+
+        // With it gone the hud lets play update, bottom first
+        stack.pop(&mut engine, &mut log);
+        take(&log);
+        stack.update(&mut engine, &mut log);
+        assert_eq!(take(&log), ["update play", "update hud"]);
+
+        // Something opaque on top hides everything under it
+        stack.push(probe("menu"), &mut engine, &mut log);
+        take(&log);
+        stack.update(&mut engine, &mut log);
+        assert_eq!(take(&log), ["update menu"]);
+        render(&stack, &engine, &log);
+        assert_eq!(take(&log), ["render menu"]);
+    }
+}
@@ -1,5 +1,4 @@
-use std::rc::Rc;
-use crate::{geom::Rect, model::Material, render::InstanceGroups};
+use crate::{assets::MaterialRef, geom::Rect, render::InstanceGroups};
 
 pub struct Letter {
     pub name: char,
@@ -7,7 +6,7 @@ pub struct Letter {
 }
 
 impl Letter {
-    pub fn draw_letter(& self, igs: &mut InstanceGroups, mat: &Rc<Material>, pos: [f32; 2]) {
+    pub fn draw_letter(& self, igs: &mut InstanceGroups, mat: MaterialRef, pos: [f32; 2]) {
         let mut ascii = self.name as u8;
         
         if ascii < 125 {
@@ -58,7 +57,7 @@ impl Sentence {
         return s;
     }
 
-    pub fn draw_sentence(&self, igs: &mut InstanceGroups, mat: &Rc<Material>) {
+    pub fn draw_sentence(&self, igs: &mut InstanceGroups, mat: MaterialRef) {
         let mut l_pos = self.position;
         for c in &self.letters {
             if c.name == '\n' {
@@ -0,0 +1,499 @@
+use crate::geom::*;
+use crate::model::Triangles;
+use std::rc::Rc;
+
+// Most triangles a BVH leaf holds
+const LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Node {
+    bounds: Box,
+    // Leaves cover tris[start..start + count].  Inner nodes have count 0,
+    // their left child right after them and their right child at start.
+    start: usize,
+    count: usize,
+}
+
+/// A pile of triangles that never moves, with a bounding volume hierarchy
+/// over it so queries only look at triangles near where they're asking.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TriMesh {
+    tris: Vec<Triangle>,
+    nodes: Vec<Node>,
+}
+
+impl TriMesh {
+    /// Every three `indices` into `positions` make a triangle
+    pub fn new(positions: &[Pos3], indices: &[u32]) -> Self {
+        let mut tris: Vec<Triangle> = indices
+            .chunks_exact(3)
+            .map(|i| Triangle {
+                a: positions[i[0] as usize],
+                b: positions[i[1] as usize],
+                c: positions[i[2] as usize],
+            })
+            .collect();
+        let mut nodes = vec![];
+        if !tris.is_empty() {
+            build(&mut tris, 0, &mut nodes);
+        }
+        TriMesh { tris, nodes }
+    }
+
+    pub fn from_triangles(triangles: &Triangles) -> Self {
+        Self::new(&triangles.positions, &triangles.indices)
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.tris
+    }
+
+    pub fn bounds(&self) -> Box {
+        self.nodes.first().map_or(
+            Box {
+                c: Pos3::origin(),
+                r: Pos3::origin(),
+            },
+            |n| n.bounds,
+        )
+    }
+
+    /// Fill `out` with the index of every triangle whose bounds overlap `b`
+    pub fn query(&self, b: &Box, out: &mut Vec<usize>) {
+        out.clear();
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(ni) = stack.pop() {
+            let node = &self.nodes[ni];
+            if !overlapping_box_box(&node.bounds, b) {
+                continue;
+            }
+            if node.count > 0 {
+                // A leaf's bounds cover all its triangles, not each one
+                out.extend(
+                    (node.start..node.start + node.count)
+                        .filter(|&i| overlapping_box_box(&self.tris[i].bounds(), b)),
+                );
+            } else {
+                stack.push(node.start);
+                stack.push(ni + 1);
+            }
+        }
+    }
+
+    /// The closest point on the mesh to p, and which triangle it's on, if
+    /// there's one within `max_dist`
+    pub fn closest_point(&self, p: Pos3, max_dist: f32) -> Option<(Pos3, usize)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut best = None;
+        let mut best_d2 = max_dist * max_dist;
+        let mut stack = vec![0];
+        while let Some(ni) = stack.pop() {
+            let node = &self.nodes[ni];
+            // Nothing in here can beat what we've got
+            if sq_dist_point_box(p, &node.bounds) > best_d2 {
+                continue;
+            }
+            if node.count > 0 {
+                for i in node.start..node.start + node.count {
+                    let q = closest_point_triangle(p, &self.tris[i]);
+                    let d2 = p.distance2(q);
+                    if d2 <= best_d2 {
+                        best_d2 = d2;
+                        best = Some((q, i));
+                    }
+                }
+            } else {
+                stack.push(node.start);
+                stack.push(ni + 1);
+            }
+        }
+        best
+    }
+
+    /// What's the offset I'd need to push s out of the mesh?  Triangles
+    /// are two-sided, so it goes whichever way s is already on.
+    pub fn disp_sphere(&self, s: &Sphere) -> Option<Vec3> {
+        let mut candidates = vec![];
+        self.query(&s.bounds(), &mut candidates);
+        if candidates.is_empty() {
+            return None;
+        }
+        // Push out of the deepest triangle, then check again, since in a
+        // corner getting out of one can leave us in another
+        let mut c = s.c;
+        let mut touching = false;
+        for _ in 0..4 {
+            let mut deepest: Option<(f32, Vec3)> = None;
+            for &i in candidates.iter() {
+                let tri = &self.tris[i];
+                let offset = c - closest_point_triangle(c, tri);
+                let dist = offset.magnitude();
+                if dist > s.r {
+                    continue;
+                }
+                let depth = s.r - dist;
+                let disp = if dist > 0.0 {
+                    offset * (depth / dist)
+                } else {
+                    tri.normal() * s.r
+                };
+                if deepest.is_none_or(|(d, _)| depth > d) {
+                    deepest = Some((depth, disp));
+                }
+            }
+            match deepest {
+                Some((_, disp)) => {
+                    c += disp;
+                    touching = true;
+                }
+                None => break,
+            }
+        }
+        if touching {
+            Some(c - s.c)
+        } else {
+            None
+        }
+    }
+
+    /// If s moves by v, how far along (0 to 1) does it first touch the mesh?
+    pub fn sweep_sphere(&self, s: &Sphere, v: Vec3) -> Option<f32> {
+        let end = Sphere { c: s.c + v, r: s.r };
+        if !overlapping_box_box(&union(&s.bounds(), &end.bounds()), &self.bounds()) {
+            return None;
+        }
+        advance_sphere(s, v, |c| self.distance(c))
+    }
+
+    fn distance(&self, p: Pos3) -> f32 {
+        self.closest_point(p, f32::INFINITY)
+            .map_or(f32::INFINITY, |(q, _)| p.distance(q))
+    }
+}
+
+impl Shape for TriMesh {
+    fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut best: Option<RayHit> = None;
+        let mut stack = vec![0];
+        while let Some(ni) = stack.pop() {
+            let node = &self.nodes[ni];
+            let limit = best.map_or(max_dist, |b| b.dist);
+            if node.bounds.cast_ray(ray, limit).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                for tri in self.tris[node.start..node.start + node.count].iter() {
+                    let limit = best.map_or(max_dist, |b| b.dist);
+                    if let Some(hit) = tri.cast_ray(ray, limit) {
+                        best = Some(hit);
+                    }
+                }
+            } else {
+                stack.push(node.start);
+                stack.push(ni + 1);
+            }
+        }
+        best
+    }
+    fn cast_sphere(&self, s: &Sphere, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        let bounds = self.bounds();
+        let reach = sweep_reach(s, max_dist, bounds.c, bounds.r.to_vec().magnitude());
+        let t = self.sweep_sphere(s, ray.dir * reach)?;
+        let dist = t * reach;
+        let c = ray.at(dist);
+        let (point, tri) = self.closest_point(c, f32::INFINITY)?;
+        Some(RayHit {
+            dist,
+            point,
+            normal: direction_or(c - point, self.tris[tri].normal()),
+        })
+    }
+}
+
+/// A `TriMesh` placed in the world.  The scale is the same along every
+/// axis, so spheres are still spheres from the mesh's point of view.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MeshCollider {
+    pub mesh: Rc<TriMesh>,
+    pub c: Pos3,
+    pub rot: Quat,
+    pub scale: f32,
+}
+
+impl MeshCollider {
+    fn local_point(&self, p: Pos3) -> Pos3 {
+        Pos3::from_vec(self.rot.invert() * (p - self.c) / self.scale)
+    }
+    fn world_point(&self, p: Pos3) -> Pos3 {
+        self.c + self.rot * (p.to_vec() * self.scale)
+    }
+    fn sphere_to_local(&self, s: &Sphere) -> Sphere {
+        Sphere {
+            c: self.local_point(s.c),
+            r: s.r / self.scale,
+        }
+    }
+
+    /// The smallest axis-aligned box containing the whole mesh
+    pub fn bounds(&self) -> Box {
+        let local = self.mesh.bounds();
+        Obb {
+            c: self.world_point(local.c),
+            rot: self.rot,
+            r: local.r.to_vec() * self.scale,
+        }
+        .bounds()
+    }
+
+    /// What's the offset I'd need to push s out of the mesh?
+    pub fn disp_sphere(&self, s: &Sphere) -> Option<Vec3> {
+        self.mesh
+            .disp_sphere(&self.sphere_to_local(s))
+            .map(|disp| self.rot * (disp * self.scale))
+    }
+
+    /// If s moves by v, how far along (0 to 1) does it first touch the mesh?
+    pub fn sweep_sphere(&self, s: &Sphere, v: Vec3) -> Option<f32> {
+        let v = self.rot.invert() * v / self.scale;
+        self.mesh.sweep_sphere(&self.sphere_to_local(s), v)
+    }
+
+    /// The closest point on the mesh to p, and the normal of the triangle
+    /// it's on
+    pub fn closest_point(&self, p: Pos3) -> Option<(Pos3, Vec3)> {
+        let (q, tri) = self
+            .mesh
+            .closest_point(self.local_point(p), f32::INFINITY)?;
+        Some((self.world_point(q), self.rot * self.mesh.tris[tri].normal()))
+    }
+}
+
+impl Shape for MeshCollider {
+    fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        let local = Ray {
+            origin: self.local_point(ray.origin),
+            dir: self.rot.invert() * ray.dir,
+        };
+        let hit = self.mesh.cast_ray(&local, max_dist / self.scale)?;
+        Some(self.hit_from_local(hit))
+    }
+    fn cast_sphere(&self, s: &Sphere, ray: &Ray, max_dist: f32) -> Option<RayHit> {
+        let local = Ray {
+            origin: self.local_point(ray.origin),
+            dir: self.rot.invert() * ray.dir,
+        };
+        let hit = self
+            .mesh
+            .cast_sphere(&self.sphere_to_local(s), &local, max_dist / self.scale)?;
+        Some(self.hit_from_local(hit))
+    }
+}
+
+impl MeshCollider {
+    fn hit_from_local(&self, hit: RayHit) -> RayHit {
+        RayHit {
+            dist: hit.dist * self.scale,
+            point: self.world_point(hit.point),
+            normal: self.rot * hit.normal,
+        }
+    }
+}
+
+fn union(a: &Box, b: &Box) -> Box {
+    let mut lo = Pos3::origin();
+    let mut hi = Pos3::origin();
+    for i in 0..3 {
+        lo[i] = (a.c[i] - a.r[i]).min(b.c[i] - b.r[i]);
+        hi[i] = (a.c[i] + a.r[i]).max(b.c[i] + b.r[i]);
+    }
+    Box {
+        c: lo.midpoint(hi),
+        r: Pos3::from_vec((hi - lo) / 2.0),
+    }
+}
+
+/// Add a node for `tris`, which start at `start` in the whole list, and
+/// everything under it
+fn build(tris: &mut [Triangle], start: usize, nodes: &mut Vec<Node>) {
+    let bounds = tris
+        .iter()
+        .skip(1)
+        .fold(tris[0].bounds(), |b, t| union(&b, &t.bounds()));
+    let me = nodes.len();
+    nodes.push(Node {
+        bounds,
+        start,
+        count: tris.len(),
+    });
+    if tris.len() <= LEAF_SIZE {
+        return;
+    }
+
+    // Split in half along whichever axis the triangles are most spread
+    // out on
+    let center = |t: &Triangle| Pos3::centroid(&[t.a, t.b, t.c]);
+    let centers = tris
+        .iter()
+        .skip(1)
+        .fold(Sphere { c: center(&tris[0]), r: 0.0 }.bounds(), |b, t| {
+            union(&b, &Sphere { c: center(t), r: 0.0 }.bounds())
+        });
+    let axis = (0..3)
+        .max_by(|&i, &j| centers.r[i].total_cmp(&centers.r[j]))
+        .unwrap();
+    tris.sort_unstable_by(|a, b| center(a)[axis].total_cmp(&center(b)[axis]));
+    let mid = tris.len() / 2;
+    let (left, right) = tris.split_at_mut(mid);
+    build(left, start, nodes);
+    let right_index = nodes.len();
+    build(right, start + mid, nodes);
+    nodes[me].start = right_index;
+    nodes[me].count = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg32;
+
+    #[test]
+    fn build_survives_nan_positions() {
+        let positions: Vec<Pos3> = (0..30)
+            .map(|i| {
+                let x = if i % 7 == 0 { f32::NAN } else { i as f32 };
+                Pos3::new(x, (i % 3) as f32, 0.0)
+            })
+            .collect();
+        let indices: Vec<u32> = (0..30).collect();
+        let mesh = TriMesh::new(&positions, &indices);
+        assert_eq!(mesh.triangles().len(), 10);
+    }
+
+    /// Triangles scattered around a 20 x 20 x 20 cube
+    fn random_mesh(rng: &mut Pcg32) -> TriMesh {
+        let mut positions = vec![];
+        for _ in 0..200 {
+            let c = Vec3::new(
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+            );
+            for _ in 0..3 {
+                let corner = Vec3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                );
+                positions.push(Pos3::from_vec(c + corner));
+            }
+        }
+        let indices: Vec<u32> = (0..positions.len() as u32).collect();
+        TriMesh::new(&positions, &indices)
+    }
+
+    #[test]
+    fn query_matches_brute_force() {
+        let mut rng = Pcg32::seed_from_u64(15);
+        let mesh = random_mesh(&mut rng);
+        let mut found = vec![];
+        for _ in 0..100 {
+            let b = Box {
+                c: Pos3::new(
+                    rng.gen_range(-12.0..12.0),
+                    rng.gen_range(-12.0..12.0),
+                    rng.gen_range(-12.0..12.0),
+                ),
+                r: Pos3::new(
+                    rng.gen_range(0.1..4.0),
+                    rng.gen_range(0.1..4.0),
+                    rng.gen_range(0.1..4.0),
+                ),
+            };
+            mesh.query(&b, &mut found);
+            found.sort_unstable();
+            let expected: Vec<usize> = (0..mesh.triangles().len())
+                .filter(|&i| overlapping_box_box(&mesh.triangles()[i].bounds(), &b))
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    /// A flat 10 x 10 floor in the xz plane, made of 200 triangles
+    fn floor() -> TriMesh {
+        let mut positions = vec![];
+        for z in 0..=10 {
+            for x in 0..=10 {
+                positions.push(Pos3::new(x as f32 - 5.0, 0.0, z as f32 - 5.0));
+            }
+        }
+        let mut indices = vec![];
+        for z in 0..10 {
+            for x in 0..10 {
+                let i = z * 11 + x;
+                indices.extend_from_slice(&[i, i + 11, i + 1, i + 1, i + 11, i + 12]);
+            }
+        }
+        TriMesh::new(&positions, &indices)
+    }
+
+    /// The floor, doubled in size, stood up on its side and moved
+    fn wall() -> MeshCollider {
+        MeshCollider {
+            mesh: Rc::new(floor()),
+            c: Pos3::new(3.0, 1.0, 2.0),
+            rot: Quat::from_angle_x(cgmath::Deg(90.0)),
+            scale: 2.0,
+        }
+    }
+
+    #[test]
+    fn collider_pushes_sphere_out_in_world_space() {
+        let wall = wall();
+        let n = wall.rot * Vec3::unit_y();
+        let along = wall.rot * Vec3::unit_x();
+        // 0.5 in front of the wall, but 1 across
+        let s = Sphere {
+            c: wall.c + n * 0.5 + along * 3.0,
+            r: 1.0,
+        };
+        let disp = wall.disp_sphere(&s).unwrap();
+        assert!((disp - n * 0.5).magnitude() < 1e-4, "{:?}", disp);
+        let (q, normal) = wall.closest_point(s.c).unwrap();
+        assert!((q - (wall.c + along * 3.0)).magnitude() < 1e-4, "{:?}", q);
+        assert!((normal.dot(n)).abs() > 0.999);
+        // Past the edge of the scaled-up wall, which is 10 across
+        let off = Sphere {
+            c: wall.c + n * 0.5 + along * 11.0,
+            r: 1.0,
+        };
+        assert_eq!(wall.disp_sphere(&off), None);
+    }
+
+    #[test]
+    fn collider_sweeps_in_world_space() {
+        let wall = wall();
+        let n = wall.rot * Vec3::unit_y();
+        let s = Sphere {
+            c: wall.c + n * 5.0 + wall.rot * Vec3::new(2.0, 0.0, 1.0),
+            r: 1.0,
+        };
+        // 4 of the 8 to go before it touches
+        let t = wall.sweep_sphere(&s, n * -8.0).unwrap();
+        assert!((t - 0.5).abs() < 1e-3, "{}", t);
+        assert_eq!(wall.sweep_sphere(&s, n * -3.0), None);
+        let hit = wall
+            .cast_sphere(&s, &Ray::new(s.c, -n), f32::INFINITY)
+            .unwrap();
+        assert!((hit.dist - 4.0).abs() < 1e-2, "{:?}", hit);
+        assert!((hit.normal - n).magnitude() < 1e-3);
+    }
+}